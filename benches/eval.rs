@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use lishp::Interpreter;
+
+const LINES: usize = 100_000;
+
+// Capturing the output of a command into a list of lines, both with stdout
+// and by reading the stream a nested command returns to the end
+fn read_output(c: &mut Criterion) {
+    let mut it = Interpreter::load();
+    let captured = format!("(stdout (seq 1 {LINES}))");
+    let streamed = format!("(list (seq 1 {LINES}))");
+
+    c.bench_function("read 100k lines", |b| {
+        b.iter(|| it.eval(&captured).unwrap())
+    });
+    c.bench_function("stream 100k lines", |b| {
+        b.iter(|| it.eval(&streamed).unwrap().len())
+    });
+}
+
+// Walking a long list with the prelude's count, which is all first, rest and
+// function calls, over both a captured list and a stream. The 25k stream is
+// there to check that walking a stream stays linear in its length
+fn count_lines(c: &mut Criterion) {
+    let mut it = Interpreter::load();
+    let captured = format!("(count (stdout (seq 1 {LINES})))");
+    let streamed = format!("(count (seq 1 {LINES}))");
+    let quarter = format!("(count (seq 1 {}))", LINES / 4);
+
+    let mut group = c.benchmark_group("count");
+    group.sample_size(10);
+    group.bench_function("count 100k lines", |b| {
+        b.iter(|| it.eval(&captured).unwrap())
+    });
+    group.bench_function("count 25k streamed lines", |b| {
+        b.iter(|| it.eval(&quarter).unwrap())
+    });
+    group.bench_function("count 100k streamed lines", |b| {
+        b.iter(|| it.eval(&streamed).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read_output, count_lines);
+criterion_main!(benches);
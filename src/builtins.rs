@@ -1,110 +1,167 @@
+use crate::env::{Lambda, Scope};
+use crate::expand::expand_one;
+use crate::interpreter::MAX_DEPTH;
+use crate::jobs::JobState;
+use crate::number::Num;
+use crate::process::lines;
 use crate::Interpreter;
 use crate::SExpression;
+use crate::Symbol;
+use crate::Vector;
+use crate::{type_error, Error, ErrorKind, Span};
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::collections::LinkedList as List;
+use std::collections::VecDeque as List;
 use std::env::set_current_dir;
 use std::env::{set_var, var};
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Read;
 use std::io::Write;
+use std::rc::Rc;
 
 use lazy_static::lazy_static;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+type Func = fn(List<SExpression>, &mut Interpreter) -> Result<SExpression, Error>;
+type SpecialForm = fn(List<SExpression>, &mut Interpreter) -> Result<Tail, Error>;
+type BinInt = fn(i64, i64) -> Option<i64>;
+type BinNum = fn(Num, Num) -> Result<Num, Error>;
+type BinCmp = fn(Ordering) -> bool;
+
+fn to_bool(e: &SExpression) -> Result<bool, Error> {
+    match e {
+        SExpression::Bool(b) => Ok(*b),
+        e => Err(type_error("bool", e)),
+    }
+}
 
-type Func = fn(List<SExpression>, &mut Interpreter) -> Result<SExpression, String>;
-type BinNum = fn(f64, f64) -> f64;
-type BinCmp = fn(f64, f64) -> bool;
-
-fn to_f64(e: SExpression) -> Result<f64, String> {
-    let name = e.ident().iter().collect::<String>();
+// Apply an arithmetic operator. Integers that fit in an i64 take a fast path,
+// and anything it gives up on, such as overflow or an inexact division, is
+// done on exact integers, ratios or floats.
+fn arith(x: SExpression, y: SExpression, i: BinInt, f: BinNum) -> Result<SExpression, Error> {
+    if let (SExpression::Int(a), SExpression::Int(b)) = (&x, &y) {
+        if let Some(n) = i(*a, *b) {
+            return Ok(SExpression::Int(n));
+        }
+    }
 
-    name.parse::<f64>()
-        .map_err(|_| format!("{} is not a number", &name))
+    Ok(f(Num::from_expr(&x)?, Num::from_expr(&y)?)?.into_expr())
 }
 
 fn fold_nums(
     args: List<SExpression>,
-    init: f64,
+    init: i64,
+    i: BinInt,
     f: BinNum,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    let mut accum = init;
+) -> Result<SExpression, Error> {
+    let mut accum = SExpression::Int(init);
 
     for arg in args {
-        let x = to_f64(s.eval_expr(arg, false)?)?;
-        accum = f(accum, x);
+        let x = s.eval_expr(arg, false)?;
+        accum = arith(accum, x, i, f)?;
     }
 
-    let accum = accum.to_string().chars().collect();
-
-    Ok(SExpression::Atom(accum))
+    Ok(accum)
 }
 
 fn bin_num(
-    x: SExpression,
-    y: SExpression,
+    name: &str,
+    mut args: List<SExpression>,
+    i: BinInt,
     f: BinNum,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    let n = f(
-        to_f64(s.eval_expr(x, false)?)?,
-        to_f64(s.eval_expr(y, false)?)?,
-    );
-
-    Ok(SExpression::Atom(n.to_string().chars().collect()))
-}
+) -> Result<SExpression, Error> {
+    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
+        let x = s.eval_expr(x, false)?;
+        let y = s.eval_expr(y, false)?;
 
-pub fn builtin_add(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
-    fold_nums(args, 0.0, |a, b| a + b, s)
+        arith(x, y, i, f)
+    } else {
+        Err(Error::arity(format!("{name} requires two arguments")))
+    }
 }
 
-pub fn builtin_sub(
+fn unary_num(
+    name: &str,
     mut args: List<SExpression>,
+    f: fn(Num) -> Result<Num, Error>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_num(x, y, |a, b| a - b, s)
+) -> Result<SExpression, Error> {
+    if let Some(x) = args.pop_front() {
+        let x = s.eval_expr(x, false)?;
+        Ok(f(Num::from_expr(&x)?)?.into_expr())
     } else {
-        Err("sub requires two arguments".to_string())
+        Err(Error::arity(format!("{name} requires one argument")))
     }
 }
 
-pub fn builtin_mul(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
-    fold_nums(args, 1.0, |a, b| a * b, s)
+pub fn builtin_add(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    fold_nums(args, 0, i64::checked_add, Num::plus, s)
 }
 
-pub fn builtin_div(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_num(x, y, |a, b| a / b, s)
-    } else {
-        Err("div requires two arguments".to_string())
-    }
+pub fn builtin_sub(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    bin_num("sub", args, i64::checked_sub, Num::minus, s)
 }
 
-pub fn builtin_mod(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_num(x, y, |a, b| a % b, s)
-    } else {
-        Err("mod requires two arguments".to_string())
-    }
+pub fn builtin_mul(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    fold_nums(args, 1, i64::checked_mul, Num::times, s)
 }
 
-pub fn builtin_pow(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_num(x, y, |a, b| a.powf(b), s)
-    } else {
-        Err("pow requires two arguments".to_string())
+// Division is exact, giving a ratio such as 7/2 when it isn't whole
+pub fn builtin_div(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    bin_num(
+        "div",
+        args,
+        |a, b| match a.checked_rem(b) {
+            Some(0) => a.checked_div(b),
+            _ => None,
+        },
+        Num::divide,
+        s,
+    )
+}
+
+pub fn builtin_rem(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    bin_num("rem", args, i64::checked_rem, Num::remainder, s)
+}
+
+// Integer division, rounding towards zero
+pub fn builtin_quot(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    bin_num("quot", args, i64::checked_div, Num::quot, s)
+}
+
+pub fn builtin_pow(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    bin_num(
+        "pow",
+        args,
+        |a, b| a.checked_pow(b.try_into().ok()?),
+        Num::pow,
+        s,
+    )
+}
+
+pub fn builtin_float(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    unary_num("float", args, |n| Ok(n.float()), s)
+}
+
+pub fn builtin_floor(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    unary_num("floor", args, Num::floor, s)
+}
+
+pub fn builtin_round(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    unary_num("round", args, Num::round, s)
+}
+
+// Order two numbers, comparing integers exactly
+fn cmp_nums(x: &SExpression, y: &SExpression) -> Result<Ordering, Error> {
+    if let (SExpression::Int(a), SExpression::Int(b)) = (x, y) {
+        return Ok(a.cmp(b));
     }
+
+    Num::from_expr(x)?.compare(&Num::from_expr(y)?)
 }
 
 fn bin_cmp(
@@ -112,429 +169,1471 @@ fn bin_cmp(
     y: SExpression,
     f: BinCmp,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    let b = f(
-        to_f64(s.eval_expr(x, false)?)?,
-        to_f64(s.eval_expr(y, false)?)?,
-    );
+) -> Result<SExpression, Error> {
+    let x = s.eval_expr(x, false)?;
+    let y = s.eval_expr(y, false)?;
 
-    Ok(SExpression::Atom(b.to_string().chars().collect()))
+    Ok(SExpression::Bool(f(cmp_nums(&x, &y)?)))
 }
 
-pub fn builtin_not(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
+pub fn builtin_not(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     if let Some(e) = args.pop_front() {
-        match s.eval_expr(e, false)? {
-            SExpression::Atom(s) => match s.iter().collect::<String>().as_str() {
-                "true" => Ok(SExpression::Atom("false".to_string().chars().collect())),
-                "false" => Ok(SExpression::Atom("true".to_string().chars().collect())),
-                _ => Err("not expects a boolean argument".to_string()),
-            },
-            _ => Err("not expects a boolean argument".to_string()),
-        }
+        Ok(SExpression::Bool(!to_bool(&s.eval_expr(e, false)?)?))
     } else {
-        Err("not requires one argument".to_string())
+        Err(Error::arity("not requires one argument"))
     }
 }
 
-pub fn builtin_or(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+pub fn builtin_or(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     let mut accum = false;
 
     for arg in args {
         let x = s.eval_expr(arg, false)?;
-        accum = accum || x.ident().iter().collect::<String>() == "true";
+        accum = to_bool(&x)? || accum;
     }
 
-    Ok(SExpression::Atom(accum.to_string().chars().collect()))
+    Ok(SExpression::Bool(accum))
 }
 
-pub fn builtin_and(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+pub fn builtin_and(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     let mut accum = true;
 
     for arg in args {
         let x = s.eval_expr(arg, false)?;
-        accum = accum && x.ident().iter().collect::<String>() == "true";
+        accum = to_bool(&x)? && accum;
     }
 
-    Ok(SExpression::Atom(accum.to_string().chars().collect()))
+    Ok(SExpression::Bool(accum))
 }
 
-pub fn builtin_lt(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+// (> file (cmd)) and (< file (cmd)) are redirections rather than comparisons
+// when the first argument isn't a number and the second is a command
+fn cmp_or_redirect(
+    name: &str,
+    op: &str,
+    mut args: List<SExpression>,
+    f: BinCmp,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let root = s.root;
+
     if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_cmp(x, y, |a, b| a.partial_cmp(&b).unwrap() == Ordering::Less, s)
+        let x = s.eval_expr(x, false)?;
+
+        if let SExpression::Call(..) = y {
+            if Num::from_expr(&x).is_err() {
+                let stages = s.redirect_job(op, expand_one(x)?, y)?;
+                return s.run_pipeline(stages, root);
+            }
+        }
+
+        let y = s.eval_expr(y, false)?;
+        Ok(SExpression::Bool(f(cmp_nums(&x, &y)?)))
     } else {
-        Err("lt requires two arguments".to_string())
+        Err(Error::arity(format!("{name} requires two arguments")))
     }
 }
 
-pub fn builtin_gt(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+pub fn builtin_lt(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    cmp_or_redirect("lt", "<", args, |o| o == Ordering::Less, s)
+}
+
+pub fn builtin_gt(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    cmp_or_redirect("gt", ">", args, |o| o == Ordering::Greater, s)
+}
+
+pub fn builtin_leq(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_cmp(
-            x,
-            y,
-            |a, b| a.partial_cmp(&b).unwrap() == Ordering::Greater,
-            s,
-        )
+        bin_cmp(x, y, |o| o != Ordering::Greater, s)
     } else {
-        Err("gt requires two arguments".to_string())
+        Err(Error::arity("leq requires two arguments"))
     }
 }
 
-pub fn builtin_leq(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
+pub fn builtin_geq(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_cmp(
-            x,
-            y,
-            |a, b| a == b || a.partial_cmp(&b).unwrap() == Ordering::Less,
-            s,
-        )
+        bin_cmp(x, y, |o| o != Ordering::Less, s)
     } else {
-        Err("leq requires two arguments".to_string())
+        Err(Error::arity("geq requires two arguments"))
     }
 }
 
-pub fn builtin_geq(
-    mut args: List<SExpression>,
-    s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
-        bin_cmp(
-            x,
-            y,
-            |a, b| a == b || a.partial_cmp(&b).unwrap() == Ordering::Greater,
-            s,
-        )
-    } else {
-        Err("geq requires two arguments".to_string())
+// Structural equality, except that numbers compare by value and a string is
+// equal to a symbol with the same text, since words passed around a shell
+// are as likely to be one as the other
+fn equal(x: &SExpression, y: &SExpression) -> bool {
+    use SExpression::*;
+
+    match (x, y) {
+        (Numeral(_, x), y) | (y, Numeral(_, x)) => equal(x, y),
+        (Int(_) | BigInt(_) | Ratio(_) | Float(_), Int(_) | BigInt(_) | Ratio(_) | Float(_)) => {
+            matches!(cmp_nums(x, y), Ok(Ordering::Equal))
+        }
+        (Symbol(a), Symbol(b)) => a == b,
+        (Str(_) | Symbol(_), Str(_) | Symbol(_)) => x.clone().ident() == y.clone().ident(),
+        (List(a), List(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b)),
+        // Streams are only read as far as the first difference, and never
+        // when compared with something that isn't a list
+        (Stream(a), Stream(b)) => equal_items(a.iter(), b.iter()),
+        (Stream(a), List(b)) | (List(b), Stream(a)) => equal_items(a.iter(), b.iter().cloned()),
+        (Stream(_), _) | (_, Stream(_)) => false,
+        (x, y) => x == y,
     }
 }
 
-pub fn builtin_eq(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+fn equal_items(
+    mut xs: impl Iterator<Item = SExpression>,
+    mut ys: impl Iterator<Item = SExpression>,
+) -> bool {
+    loop {
+        match (xs.next(), ys.next()) {
+            (None, None) => return true,
+            (Some(x), Some(y)) if equal(&x, &y) => {}
+            _ => return false,
+        }
+    }
+}
+
+pub fn builtin_eq(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
     if let (Some(x), Some(y)) = (args.pop_front(), args.pop_front()) {
         let x = s.eval_expr(x, false)?;
         let y = s.eval_expr(y, false)?;
 
-        // Special case where "" should equal '()
+        Ok(SExpression::Bool(equal(&x, &y)))
+    } else {
+        Err(Error::arity("eq requires two arguments"))
+    }
+}
+
+// Type predicates such as (number? x)
+fn is_type(
+    name: &str,
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+    pred: fn(&SExpression) -> bool,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        Ok(SExpression::Bool(pred(&s.eval_expr(e, false)?.plain())))
+    } else {
+        Err(Error::arity(format!("{name}? requires one argument")))
+    }
+}
+
+pub fn builtin_is_number(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("number", args, s, |e| {
+        matches!(
+            e,
+            SExpression::Int(_)
+                | SExpression::BigInt(_)
+                | SExpression::Ratio(_)
+                | SExpression::Float(_)
+        )
+    })
+}
+
+pub fn builtin_is_int(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    is_type("int", args, s, |e| {
+        matches!(e, SExpression::Int(_) | SExpression::BigInt(_))
+    })
+}
+
+pub fn builtin_is_ratio(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("ratio", args, s, |e| matches!(e, SExpression::Ratio(_)))
+}
+
+pub fn builtin_is_float(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("float", args, s, |e| matches!(e, SExpression::Float(_)))
+}
+
+pub fn builtin_is_bool(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    is_type("bool", args, s, |e| matches!(e, SExpression::Bool(_)))
+}
+
+pub fn builtin_is_string(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("string", args, s, |e| matches!(e, SExpression::Str(_)))
+}
+
+pub fn builtin_is_symbol(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("symbol", args, s, |e| matches!(e, SExpression::Symbol(_)))
+}
+
+pub fn builtin_is_list(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    is_type("list", args, s, |e| {
+        matches!(e, SExpression::List(_) | SExpression::Stream(_))
+    })
+}
+
+pub fn builtin_is_nil(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    is_type("nil", args, s, |e| matches!(e, SExpression::Nil))
+}
+
+pub fn builtin_is_function(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    is_type("function", args, s, |e| {
+        matches!(e, SExpression::Function(_))
+    })
+}
+
+pub fn builtin_type_of(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let e = s.eval_expr(e, false)?;
+        Ok(SExpression::Symbol(e.type_name().into()))
+    } else {
+        Err(Error::arity("type-of requires one argument"))
+    }
+}
 
-        match (&x, &y) {
-            (SExpression::Atom(s), SExpression::List(l))
-            | (SExpression::List(l), SExpression::Atom(s)) => {
-                if s.is_empty() && l.is_empty() {
-                    return Ok(SExpression::Atom("true".to_string().chars().collect()));
+// Parse a string, such as a line of command output, into a number
+pub fn builtin_number(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        match s.eval_expr(e, false)? {
+            e @ (SExpression::Str(_) | SExpression::Symbol(_)) => {
+                match Num::parse(e.clone().ident().trim()) {
+                    Some(n) => Ok(n.into_expr()),
+                    None => Err(type_error("number", &e)),
                 }
             }
-            _ => {}
+            e => Num::from_expr(&e).map(Num::into_expr),
         }
+    } else {
+        Err(Error::arity("number requires one argument"))
+    }
+}
 
-        Ok(SExpression::Atom((x == y).to_string().chars().collect()))
+pub fn builtin_string(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        Ok(SExpression::Str(s.eval_expr(e, false)?.ident().into()))
     } else {
-        Err("eq requires two arguments".to_string())
+        Err(Error::arity("string requires one argument"))
     }
 }
 
-pub fn builtin_if(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
+// What a special form evaluates to. Forms with a tail position hand the
+// expression in it back to the evaluator instead of evaluating it themselves,
+// so that loops written as recursion don't grow the stack.
+pub enum Tail {
+    Value(SExpression),
+    Eval(SExpression),
+}
+
+pub fn builtin_if(mut args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
     if let (Some(cond), Some(t), Some(f)) = (args.pop_front(), args.pop_front(), args.pop_front()) {
-        if s.eval_expr(cond, false)?.ident().iter().collect::<String>() == "true" {
-            s.eval_expr(t, false)
+        if to_bool(&s.eval_expr(cond, false)?)? {
+            Ok(Tail::Eval(t))
         } else {
-            s.eval_expr(f, false)
+            Ok(Tail::Eval(f))
         }
     } else {
-        Err("if requires three arguments".to_string())
+        Err(Error::arity("if requires three arguments"))
+    }
+}
+
+// Evaluate expressions in order, returning the value of the last. Commands
+// among them run the way they would where the form was called.
+fn eval_body(body: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let mut val = SExpression::Nil;
+    for e in body {
+        s.finish(&val);
+        val = s.eval_expr(e, s.root)?;
+    }
+    Ok(val)
+}
+
+// Like eval_body, but leaves the last expression to the evaluator
+fn tail_body(mut body: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let last = body.pop_back().unwrap_or(SExpression::Nil);
+    let val = eval_body(body, s)?;
+    s.finish(&val);
+    Ok(Tail::Eval(last))
+}
+
+// (do expr...) evaluates expressions in order, returning the value of the last
+pub fn builtin_do(args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    tail_body(args, s)
+}
+
+pub fn builtin_when(mut args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let Some(cond) = args.pop_front() else {
+        return Err(Error::arity("when requires a condition"));
+    };
+
+    if to_bool(&s.eval_expr(cond, false)?)? {
+        tail_body(args, s)
+    } else {
+        Ok(Tail::Value(SExpression::Nil))
     }
 }
 
+pub fn builtin_unless(mut args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let Some(cond) = args.pop_front() else {
+        return Err(Error::arity("unless requires a condition"));
+    };
+
+    if to_bool(&s.eval_expr(cond, false)?)? {
+        Ok(Tail::Value(SExpression::Nil))
+    } else {
+        tail_body(args, s)
+    }
+}
+
+// Split a clause such as (test body...) into its head and body
+fn clause(e: SExpression, form: &str) -> Result<(SExpression, List<SExpression>), Error> {
+    match e {
+        SExpression::Call(mut es, _) if !es.is_empty() => {
+            let head = es.pop_front().unwrap();
+            Ok((head, es))
+        }
+        e => Err(Error::new(
+            ErrorKind::Type,
+            format!("invalid {form} clause {e}"),
+        )),
+    }
+}
+
+fn is_else(e: &SExpression) -> bool {
+    matches!(e, SExpression::Symbol(s) if s.name().as_ref() == "else")
+}
+
+// (cond (test body...)... (else body...)) evaluates the body of the first
+// clause whose test is true
+pub fn builtin_cond(args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    for c in args {
+        let (test, body) = clause(c, "cond")?;
+
+        if is_else(&test) || to_bool(&s.eval_expr(test, false)?)? {
+            return tail_body(body, s);
+        }
+    }
+
+    Ok(Tail::Value(SExpression::Nil))
+}
+
+// (case key (value body...)... ((value...) body...)... (else body...))
+// evaluates the body of the first clause with a value equal to key. The
+// values aren't evaluated.
+pub fn builtin_case(mut args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let Some(key) = args.pop_front() else {
+        return Err(Error::arity("case requires a key"));
+    };
+    let key = s.eval_expr(key, false)?;
+
+    for c in args {
+        let (values, body) = clause(c, "case")?;
+
+        let matched = match values {
+            v if is_else(&v) => true,
+            SExpression::Call(vs, _) => vs.iter().any(|v| equal(v, &key)),
+            SExpression::List(vs) => vs.iter().any(|v| equal(v, &key)),
+            v => equal(&v, &key),
+        };
+        if matched {
+            return tail_body(body, s);
+        }
+    }
+
+    Ok(Tail::Value(SExpression::Nil))
+}
+
+// (while test body...) evaluates body for as long as test is true
+pub fn builtin_while(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let Some(test) = args.pop_front() else {
+        return Err(Error::arity("while requires a condition"));
+    };
+
+    while to_bool(&s.eval_expr(test.clone(), false)?)? {
+        let val = eval_body(args.clone(), s)?;
+        s.finish(&val);
+    }
+
+    Ok(SExpression::Nil)
+}
+
+// Evaluate body once for each value, with var bound to it in a fresh scope
+fn each(
+    var: Symbol,
+    values: impl Iterator<Item = SExpression>,
+    body: &List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<(), Error> {
+    let env = s.env.clone();
+
+    for v in values {
+        s.env = Scope::new(Some(env.clone()));
+        Scope::define(&s.env, var, v);
+
+        let res = eval_body(body.clone(), s);
+        s.env = env.clone();
+        s.finish(&res?);
+    }
+
+    Ok(())
+}
+
+// (for var list body...) evaluates body for each item of a list, such as the
+// lines of a command's output
+pub fn builtin_for(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let (Some(SExpression::Symbol(var)), Some(list)) = (args.pop_front(), args.pop_front()) else {
+        return Err(Error::arity("for requires a name, a list and a body"));
+    };
+
+    let list = s.eval_expr(list, false)?;
+    each_item(var, list, &args, s)
+}
+
+// Evaluate body for each item of a list or stream
+fn each_item(
+    var: Symbol,
+    list: SExpression,
+    body: &List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    match list {
+        SExpression::List(items) => each(var, items.into_iter(), body, s)?,
+        SExpression::Stream(lines) => each(var, lines.into_items(), body, s)?,
+        SExpression::Nil => {}
+        e => return Err(type_error("list", &e)),
+    }
+
+    Ok(SExpression::Nil)
+}
+
+// (for-each f list) calls f on each item of a list for its side effects. Like
+// for, it loops rather than recursing, so it works on any length of output.
+pub fn builtin_for_each(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let (Some(f), Some(list), None) = (args.pop_front(), args.pop_front(), args.pop_front()) else {
+        return Err(Error::arity("for-each requires a function and a list"));
+    };
+    let f = s.eval_expr(f, false)?;
+    let list = s.eval_expr(list, false)?;
+
+    // The name can't be written in code, so it can't shadow anything f uses
+    let var = Symbol::new("for-each item");
+    let call = SExpression::Call(List::from([f, SExpression::Symbol(var)]), Span::default());
+
+    each_item(var, list, &List::from([call]), s)
+}
+
+// (dotimes (var n) body...) evaluates body with var counting from 0 to n - 1
+pub fn builtin_dotimes(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let Some(SExpression::Call(mut spec, _)) = args.pop_front() else {
+        return Err(Error::arity("dotimes requires (name count) and a body"));
+    };
+    let (Some(SExpression::Symbol(var)), Some(n), None) =
+        (spec.pop_front(), spec.pop_front(), spec.pop_front())
+    else {
+        return Err(Error::arity("dotimes requires (name count) and a body"));
+    };
+
+    let n = match s.eval_expr(n, false)?.plain() {
+        SExpression::Int(n) => n,
+        e => return Err(type_error("int", &e)),
+    };
+    each(var, (0..n).map(SExpression::Int), &args, s)?;
+
+    Ok(SExpression::Nil)
+}
+
 pub fn builtin_first(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let Some(e) = args.pop_front() {
         match s.eval_expr(e, false)? {
-            SExpression::List(mut es) => {
-                if let Some(e) = es.pop_front() {
-                    Ok(e)
+            SExpression::List(es) => {
+                if let Some(e) = es.first() {
+                    Ok(e.clone())
                 } else {
-                    Err("tried to call first on empty list".to_string())
+                    Err("tried to call first on empty list".into())
                 }
             }
-            SExpression::Atom(mut s) => {
-                if let Some(c) = s.pop_front() {
-                    let mut l = List::new();
-                    l.push_front(c);
-                    Ok(SExpression::Atom(l))
+            SExpression::Stream(lines) => match lines.first() {
+                Some(line) => Ok(line),
+                None => Err("tried to call first on empty list".into()),
+            },
+            SExpression::Str(s) => {
+                if let Some(c) = s.chars().next() {
+                    Ok(SExpression::Str(c.to_string().into()))
                 } else {
-                    Err("tried to call first on empty string".to_string())
+                    Err("tried to call first on empty string".into())
                 }
             }
-            _ => unreachable!(),
+            e => Err(type_error("list or string", &e)),
         }
     } else {
-        Err("first requires one argument".to_string())
+        Err(Error::arity("first requires one argument"))
     }
 }
 
 pub fn builtin_rest(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let Some(e) = args.pop_front() {
         match s.eval_expr(e, false)? {
-            SExpression::List(mut es) => {
-                es.pop_front();
-
-                Ok(SExpression::List(es))
+            SExpression::List(es) => Ok(SExpression::List(es.rest())),
+            SExpression::Stream(lines) => Ok(SExpression::Stream(lines.rest())),
+            SExpression::Str(s) => {
+                let skip = s.chars().next().map_or(0, |c| c.len_utf8());
+                Ok(SExpression::Str(s[skip..].into()))
             }
-            SExpression::Atom(mut s) => {
-                s.pop_front();
-                Ok(SExpression::Atom(s))
-            }
-            _ => unreachable!(),
+            e => Err(type_error("list or string", &e)),
         }
     } else {
-        Err("rest requires one argument".to_string())
+        Err(Error::arity("rest requires one argument"))
     }
 }
 
-pub fn builtin_list(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
-    let mut l = List::new();
+pub fn builtin_list(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let mut l = vec![];
 
     for arg in args {
-        l.push_back(s.eval_expr(arg, false)?);
+        l.push(s.eval_expr(arg, false)?);
     }
 
-    Ok(SExpression::List(l))
+    Ok(SExpression::List(l.into()))
+}
+
+// (def name val) defines a global variable, replacing any earlier value
+pub fn builtin_def(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(val)) = (args.pop_front(), args.pop_front()) {
+        Scope::check_const(&s.globals, name)?;
+        let val = s.eval_expr(val, false)?;
+        Scope::define(&s.globals, name, val);
+        Ok(SExpression::Str(format!("defined {name}").into()))
+    } else {
+        Err(Error::arity("def requires two arguments"))
+    }
 }
 
-pub fn builtin_def(
+pub fn builtin_defun(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(SExpression::Atom(name)), Some(val)) = (args.pop_front(), args.pop_front()) {
-        s.defs.insert(name.clone(), val);
-        Ok(SExpression::Atom(
-            format!("defined {}", name.iter().collect::<String>())
-                .chars()
-                .collect(),
-        ))
+) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(vars), Some(body)) =
+        (args.pop_front(), args.pop_front(), args.pop_front())
+    {
+        let f = Lambda::new(Some(name), vars, body, s.env.clone())?;
+
+        Scope::redefine(&s.globals, name, SExpression::Function(Rc::new(f)))?;
+        Ok(SExpression::Str(format!("defined {name}").into()))
     } else {
-        Err("def requires two arguments".to_string())
+        Err(Error::arity("defun requires three arguments"))
     }
 }
 
-pub fn builtin_defun(
+// (defmacro name (params...) body) defines a macro, which is called with its
+// arguments unevaluated and returns the code to evaluate in its place
+pub fn builtin_defmacro(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let (Some(SExpression::Atom(name)), Some(vars), Some(mut tree)) =
+) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(vars), Some(body)) =
         (args.pop_front(), args.pop_front(), args.pop_front())
     {
-        let mut vs = Vec::new();
-
-        // rename variables to be unique...
-        for var in vars.list() {
-            let var_name = var.iter().collect::<String>();
-            let new = format!("#{}#{}#", var_name, s.id)
-                .chars()
-                .collect::<List<char>>();
-            vs.push(new.clone());
-            tree = tree.replace(&var, SExpression::Atom(new));
-            s.id += 1;
+        let mut f = Lambda::new(Some(name), vars, body, s.env.clone())?;
+        f.is_macro = true;
+
+        Scope::redefine(&s.globals, name, SExpression::Function(Rc::new(f)))?;
+        Ok(SExpression::Str(format!("defined {name}").into()))
+    } else {
+        Err(Error::arity("defmacro requires three arguments"))
+    }
+}
+
+// Whether e is a call to the form with the given name, such as (unquote x)
+fn is_form(e: &SExpression, name: &str) -> bool {
+    match e {
+        SExpression::Call(es, _) => {
+            matches!(es.front(), Some(SExpression::Symbol(h)) if h.name().as_ref() == name)
         }
+        _ => false,
+    }
+}
+
+// Fill in the unquoted parts of a quasiquoted template. Calls inside the
+// template stay calls, so the result can be evaluated as code.
+fn quasiquote(e: SExpression, s: &mut Interpreter) -> Result<SExpression, Error> {
+    if is_form(&e, "unquote") {
+        let SExpression::Call(mut es, _) = e else {
+            unreachable!()
+        };
+        return match (es.pop_front(), es.pop_front(), es.pop_front()) {
+            (_, Some(e), None) => s.eval_expr(e, false),
+            _ => Err(Error::arity("unquote requires one argument")),
+        };
+    }
+
+    let items: Vec<SExpression> = match &e {
+        SExpression::Call(es, _) => es.iter().cloned().collect(),
+        SExpression::List(es) => es.iter().cloned().collect(),
+        _ => return Ok(e),
+    };
+
+    let mut out = vec![];
+    for item in items {
+        if is_form(&item, "unquote-splicing") {
+            let SExpression::Call(mut es, _) = item else {
+                unreachable!()
+            };
+            let (_, Some(e), None) = (es.pop_front(), es.pop_front(), es.pop_front()) else {
+                return Err(Error::arity("unquote-splicing requires one argument"));
+            };
+
+            match s.eval_expr(e, false)? {
+                SExpression::List(es) => out.extend(es),
+                SExpression::Stream(lines) => out.extend(lines.iter()),
+                SExpression::Nil => {}
+                e => return Err(type_error("list", &e)),
+            }
+        } else {
+            out.push(quasiquote(item, s)?);
+        }
+    }
+
+    Ok(match e {
+        SExpression::Call(_, span) => SExpression::Call(out.into_iter().collect(), span),
+        _ => SExpression::List(out.into()),
+    })
+}
+
+// (quasiquote template), written `template, quotes a template with parts
+// filled in by (unquote x) and (unquote-splicing x), written ,x and ,@x
+pub fn builtin_quasiquote(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        // Like ', a quasiquoted call is read as a list
+        Ok(match quasiquote(e, s)? {
+            SExpression::Call(es, _) => SExpression::List(es.into_iter().collect()),
+            e => e,
+        })
+    } else {
+        Err(Error::arity("quasiquote requires one argument"))
+    }
+}
 
-        s.funcs.insert(name.clone(), (vs, tree));
-        Ok(SExpression::Atom(
-            format!("defined {}", name.iter().collect::<String>())
-                .chars()
-                .collect(),
-        ))
+pub fn builtin_unquote(_: List<SExpression>, _: &mut Interpreter) -> Result<SExpression, Error> {
+    Err("unquote outside of quasiquote".into())
+}
+
+// (lambda (params...) body) creates an anonymous function that closes over
+// the scope it is created in
+pub fn builtin_lambda(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let (Some(vars), Some(body)) = (args.pop_front(), args.pop_front()) {
+        let f = Lambda::new(None, vars, body, s.env.clone())?;
+        Ok(SExpression::Function(Rc::new(f)))
     } else {
-        Err("defun requires three arguments".to_string())
+        Err(Error::arity("lambda requires two arguments"))
     }
 }
 
 pub fn builtin_alias(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    if let Some(SExpression::Atom(from)) = args.pop_front() {
-        let to = args.into_iter().map(|e| e.ident()).collect::<List<_>>();
+) -> Result<SExpression, Error> {
+    if let Some(SExpression::Symbol(from)) = args.pop_front() {
+        let to = args.into_iter().map(|e| e.ident()).collect::<Vec<_>>();
 
-        s.aliases.insert(from.clone(), to.clone());
+        s.aliases.insert(from.to_string(), to);
 
-        Ok(SExpression::Atom(
-            format!("created alias").chars().collect(),
-        ))
+        Ok(SExpression::Str("created alias".into()))
     } else {
-        Err("alias requires two arguments".to_string())
+        Err(Error::arity("alias requires two arguments"))
     }
 }
 
 pub fn builtin_cons(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let (Some(x), Some(xs)) = (args.pop_front(), args.pop_front()) {
         let x = s.eval_expr(x, false)?;
         let xs = s.eval_expr(xs, false)?;
 
         match (x, xs) {
-            (SExpression::Atom(c), SExpression::List(xs)) if c.len() == 1 && xs.len() == 0 => {
-                Ok(SExpression::Atom(c))
+            // Consing characters builds up a string
+            (SExpression::Str(c), SExpression::List(xs))
+                if c.chars().count() == 1 && xs.is_empty() =>
+            {
+                Ok(SExpression::Str(c))
             }
-            (SExpression::Atom(mut c), SExpression::Atom(mut s)) if c.len() == 1 => {
-                c.append(&mut s);
-                Ok(SExpression::Atom(c))
+            (SExpression::Str(c), SExpression::Str(s)) if c.chars().count() == 1 => {
+                Ok(SExpression::Str(format!("{c}{s}").into()))
             }
-            (x, SExpression::List(mut xs)) => {
-                xs.push_front(s.eval_expr(x, false)?);
-                Ok(SExpression::List(xs))
-            }
-            (_, _) => Err("cons second argument must be list-like".to_string()),
+            (x, SExpression::List(xs)) => Ok(SExpression::List(xs.cons(x))),
+            (x, SExpression::Stream(lines)) => Ok(SExpression::Stream(lines.cons(x))),
+            (_, _) => Err("cons second argument must be list-like".into()),
         }
     } else {
-        Err("cons requires two arguments".to_string())
+        Err(Error::arity("cons requires two arguments"))
     }
 }
 
-pub fn builtin_cd(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, String> {
-    if let Some(e) = args.pop_front() {
-        set_current_dir(s.eval_expr(e, false)?.ident().iter().collect::<String>())
-            .map_err(|e| format!("Failed to change directory: {e}"))?;
+// (cd [dir]) changes directory, by default to $HOME
+pub fn builtin_cd(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let dir = match args.pop_front() {
+        Some(e) => expand_one(s.eval_expr(e, false)?)?,
+        None => var("HOME").map_err(|_| Error::new(ErrorKind::Unbound, "cd: HOME is not set"))?,
+    };
+
+    set_current_dir(dir).map_err(|e| Error::io(format!("Failed to change directory: {e}")))?;
+
+    Ok(SExpression::Nil)
+}
+
+// (exit [status]) exits the shell, by default with status 0
+pub fn builtin_exit(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let status = match args.pop_front() {
+        Some(e) => match s.eval_expr(e, false)?.plain() {
+            SExpression::Int(n) => n as i32,
+            e => return Err(type_error("int", &e)),
+        },
+        None => 0,
+    };
+
+    std::process::exit(status)
+}
+
+// Bind variables in a new scope and evaluate a body in it. The bindings are
+// written either ((var val)...) or (var val...). With sequential set, as in
+// let*, each value can refer to the variables bound before it.
+fn let_form(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+    sequential: bool,
+) -> Result<Tail, Error> {
+    let Some(SExpression::Call(bindings, _)) = args.pop_front() else {
+        return Err(Error::arity("let requires a list of bindings and a body"));
+    };
+
+    let mut pairs = vec![];
+    if matches!(bindings.front(), Some(SExpression::Call(..))) {
+        for binding in bindings {
+            match binding {
+                SExpression::Call(mut b, _) if b.len() == 2 => {
+                    pairs.extend([b.pop_front().unwrap(), b.pop_front().unwrap()]);
+                }
+                b => return Err(Error::new(ErrorKind::Type, format!("invalid binding {b}"))),
+            }
+        }
     } else {
-        set_current_dir("/home/devin").map_err(|e| format!("Failed to change directory: {e}"))?;
+        pairs.extend(bindings);
     }
 
-    Ok(SExpression::Atom(List::new()))
+    if pairs.len() % 2 != 0 {
+        return Err(Error::arity(
+            "let bindings must be pairs of a name and a value",
+        ));
+    }
+
+    let scope = Scope::new(Some(s.env.clone()));
+    let mut pairs = pairs.into_iter();
+    while let (Some(name), Some(val)) = (pairs.next(), pairs.next()) {
+        let SExpression::Symbol(name) = name else {
+            return Err(type_error("symbol", &name));
+        };
+
+        let val = if sequential {
+            let env = std::mem::replace(&mut s.env, scope.clone());
+            let val = s.eval_expr(val, false);
+            s.env = env;
+            val?
+        } else {
+            s.eval_expr(val, false)?
+        };
+        Scope::define(&scope, name, val);
+    }
+
+    // The last expression of the body is left to the evaluator, which puts
+    // the outer scope back once it is done
+    s.env = scope;
+    tail_body(args, s)
+}
+
+// (let ((var val)...) body...)
+pub fn builtin_let(args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let_form(args, s, false)
+}
+
+// (let* ((var val)...) body...), where each value can use the earlier ones
+pub fn builtin_let_star(args: List<SExpression>, s: &mut Interpreter) -> Result<Tail, Error> {
+    let_form(args, s, true)
 }
 
-pub fn builtin_exit(_: List<SExpression>, _: &mut Interpreter) -> Result<SExpression, String> {
-    std::process::exit(0)
+// (set! var val) changes the value of an existing variable
+pub fn builtin_set(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(val), None) =
+        (args.pop_front(), args.pop_front(), args.pop_front())
+    {
+        let val = s.eval_expr(val, false)?;
+        Scope::set(&s.env, name, val.clone())?;
+        Ok(val)
+    } else {
+        Err(Error::arity("set! requires a name and a value"))
+    }
 }
 
-pub fn builtin_let(
+// (defvar name val) defines a global variable, evaluating val only if it
+// isn't defined yet so that loading a file again doesn't reset it
+pub fn builtin_defvar(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
-    // let statement looks like: (let ((var val)...) expr)
-    if let (Some(SExpression::Call(mut pairs)), Some(mut e)) = (args.pop_front(), args.pop_front())
-    {
-        while let (Some(SExpression::Atom(from)), Some(to)) = (pairs.pop_front(), pairs.pop_front())
-        {
-            let to = s.eval_expr(to, false)?;
-            e = e.replace(&from, to);
+) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(val)) = (args.pop_front(), args.pop_front()) {
+        Scope::check_const(&s.globals, name)?;
+        if !s.globals.borrow().vars.contains_key(&name) {
+            let val = s.eval_expr(val, false)?;
+            Scope::define(&s.globals, name, val);
         }
-        return s.eval_expr(e, false);
+        Ok(SExpression::Str(format!("defined {name}").into()))
+    } else {
+        Err(Error::arity("defvar requires two arguments"))
     }
+}
 
-    Err("let requires two arguments".to_string())
+// (defconst name val) defines a global that set! can't change
+pub fn builtin_defconst(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let (Some(SExpression::Symbol(name)), Some(val)) = (args.pop_front(), args.pop_front()) {
+        let val = s.eval_expr(val, false)?;
+        Scope::define(&s.globals, name, val);
+        s.globals.borrow_mut().consts.insert(name);
+        Ok(SExpression::Str(format!("defined {name}").into()))
+    } else {
+        Err(Error::arity("defconst requires two arguments"))
+    }
 }
 
 pub fn builtin_getenv(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let Some(a) = args.pop_front() {
-        let v: String = s.eval_expr(a, false)?.ident().into_iter().collect();
-        let val: List<char> = var(&v)
-            .map_err(|_| "env var not found".to_string())?
-            .chars()
-            .collect();
-        Ok(SExpression::Atom(val))
+        let v: String = s.eval_expr(a, false)?.ident();
+        let val = var(&v).map_err(|_| Error::new(ErrorKind::Unbound, "env var not found"))?;
+        Ok(SExpression::Str(val.into()))
     } else {
-        Err("getenv requires one argument".to_string())
+        Err(Error::arity("getenv requires one argument"))
     }
 }
 
 pub fn builtin_export(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let (Some(a), Some(b)) = (args.pop_front(), args.pop_front()) {
-        let var: String = s.eval_expr(a, false)?.ident().into_iter().collect();
-        let val: String = s.eval_expr(b, false)?.ident().into_iter().collect();
+        let var: String = s.eval_expr(a, false)?.ident();
+        let val: String = s.eval_expr(b, false)?.ident();
         set_var(&var, &val);
 
-        return Ok(SExpression::Atom(List::new()));
+        return Ok(SExpression::Nil);
     }
 
-    Err("export requires two arguments".to_string())
+    Err(Error::arity("export requires two arguments"))
 }
 
 pub fn builtin_file_write(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let (Some(content), Some(file)) = (args.pop_front(), args.pop_front()) {
-        let content: String = s.eval_expr(content, false)?.ident().into_iter().collect();
-        let file: String = s.eval_expr(file, false)?.ident().into_iter().collect();
+        let content = s.eval_expr(content, false)?.into_bytes();
+        let file: String = s.eval_expr(file, false)?.ident();
 
         let f = File::options()
             .create(true)
             .write(true)
             .truncate(true)
             .open(file)
-            .map_err(|e| format!("write: {e}"))?;
+            .map_err(|e| Error::io(format!("write: {e}")))?;
         let mut writer = BufWriter::new(f);
 
         writer
-            .write_all(content.as_bytes())
-            .map_err(|e| format!("write: {e}"))?;
+            .write_all(&content)
+            .map_err(|e| Error::io(format!("write: {e}")))?;
 
-        return Ok(SExpression::Atom(List::new()));
+        return Ok(SExpression::Nil);
     }
 
-    Err("write requires two arguments".to_string())
+    Err(Error::arity("write requires two arguments"))
 }
 
 pub fn builtin_file_append(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let (Some(content), Some(file)) = (args.pop_front(), args.pop_front()) {
-        let content: String = s.eval_expr(content, false)?.ident().into_iter().collect();
-        let file: String = s.eval_expr(file, false)?.ident().into_iter().collect();
+        let content = s.eval_expr(content, false)?.into_bytes();
+        let file: String = s.eval_expr(file, false)?.ident();
 
         let f = File::options()
             .append(true)
             .create(true)
             .open(file)
-            .map_err(|e| format!("append: {e}"))?;
+            .map_err(|e| Error::io(format!("append: {e}")))?;
 
         let mut writer = BufWriter::new(f);
 
         writer
-            .write_all(content.as_bytes())
-            .map_err(|e| format!("append: {e}"))?;
+            .write_all(&content)
+            .map_err(|e| Error::io(format!("append: {e}")))?;
 
-        return Ok(SExpression::Atom(List::new()));
+        return Ok(SExpression::Nil);
     }
 
-    Err("append requires two arguments".to_string())
+    Err(Error::arity("append requires two arguments"))
 }
 
 pub fn builtin_file_read(
     mut args: List<SExpression>,
     s: &mut Interpreter,
-) -> Result<SExpression, String> {
+) -> Result<SExpression, Error> {
     if let Some(file) = args.pop_front() {
-        let file: String = s.eval_expr(file, false)?.ident().into_iter().collect();
-        let mut f = File::open(file).map_err(|e| format!("read: {e}"))?;
+        let file: String = s.eval_expr(file, false)?.ident();
+        let mut f = File::open(file).map_err(|e| Error::io(format!("read: {e}")))?;
         let mut out = String::new();
         f.read_to_string(&mut out)
-            .map_err(|e| format!("read: {e}"))?;
+            .map_err(|e| Error::io(format!("read: {e}")))?;
+
+        return Ok(SExpression::Str(out.into()));
+    }
+
+    Err(Error::arity("read requires one argument"))
+}
+
+pub fn builtin_pipe(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let root = s.root;
+
+    if args.is_empty() {
+        return Err(Error::arity("pipe requires at least one command"));
+    }
+
+    let mut stages = vec![];
+    for arg in args {
+        stages.extend(s.job(arg)?);
+    }
+
+    s.run_pipeline(stages, root)
+}
+
+fn redirect(
+    op: &str,
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let root = s.root;
+
+    if let (Some(file), Some(inner)) = (args.pop_front(), args.pop_front()) {
+        let file = expand_one(s.eval_expr(file, false)?)?;
+        let stages = s.redirect_job(op, file, inner)?;
+        s.run_pipeline(stages, root)
+    } else {
+        Err(Error::arity(format!("{op} requires a file and a command")))
+    }
+}
+
+pub fn builtin_redirect_append(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    redirect(">>", args, s)
+}
+
+pub fn builtin_redirect_stderr(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    redirect("2>", args, s)
+}
+
+pub fn builtin_redirect_all(
+    args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    redirect("&>", args, s)
+}
+
+pub fn builtin_stdout(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let stages = s.job(e)?;
+        let out = s.exec_pipeline(stages, true, false)?;
+        Ok(lines(&out.stdout.unwrap_or_default()))
+    } else {
+        Err(Error::arity("stdout requires one argument"))
+    }
+}
+
+pub fn builtin_stderr(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let stages = s.job(e)?;
+        let out = s.exec_pipeline(stages, false, true)?;
+        Ok(lines(&out.stderr.unwrap_or_default()))
+    } else {
+        Err(Error::arity("stderr requires one argument"))
+    }
+}
+
+// Capture the stdout of a command exactly as it was written
+pub fn builtin_bytes(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let stages = s.job(e)?;
+        let out = s.exec_pipeline(stages, true, false)?;
+        Ok(SExpression::Bytes(out.stdout.unwrap_or_default().into()))
+    } else {
+        Err(Error::arity("bytes requires one argument"))
+    }
+}
+
+// Send a value to the stdin of a command: (<<< data (cmd))
+pub fn builtin_feed(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let root = s.root;
+
+    if let (Some(data), Some(inner), None) = (args.pop_front(), args.pop_front(), args.pop_front())
+    {
+        let data = s.eval_expr(data, false)?;
+        let stages = s.feed_job(data, inner)?;
+        s.run_pipeline(stages, root)
+    } else {
+        Err(Error::arity("<<< requires an input and a command"))
+    }
+}
+
+// The text encodings that decode and encode understand
+enum Encoding {
+    Utf8,
+    Latin1,
+}
+
+// Evaluate an optional encoding name, which defaults to utf-8
+fn encoding(e: Option<SExpression>, s: &mut Interpreter) -> Result<Encoding, Error> {
+    let name = match e {
+        Some(e) => s.eval_expr(e, false)?.ident().to_lowercase(),
+        None => return Ok(Encoding::Utf8),
+    };
+
+    match name.as_str() {
+        "utf-8" | "utf8" => Ok(Encoding::Utf8),
+        "latin-1" | "latin1" => Ok(Encoding::Latin1),
+        _ => Err(format!("unknown encoding {name}").into()),
+    }
+}
+
+// Turn bytes into a string: (decode b [encoding])
+pub fn builtin_decode(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let Some(e) = args.pop_front() else {
+        return Err(Error::arity(
+            "decode requires bytes and an optional encoding",
+        ));
+    };
+    let b = match s.eval_expr(e, false)? {
+        SExpression::Bytes(b) => b,
+        e => return Err(type_error("bytes", &e)),
+    };
 
-        return Ok(SExpression::Atom(out.chars().collect()));
+    let text = match encoding(args.pop_front(), s)? {
+        Encoding::Latin1 => b.iter().map(|&c| c as char).collect(),
+        Encoding::Utf8 => std::str::from_utf8(&b)
+            .map_err(|e| format!("decode: invalid utf-8 at byte {}", e.valid_up_to()))?
+            .to_string(),
+    };
+    Ok(SExpression::Str(text.into()))
+}
+
+// Turn a string into bytes: (encode str [encoding])
+pub fn builtin_encode(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let Some(e) = args.pop_front() else {
+        return Err(Error::arity(
+            "encode requires a string and an optional encoding",
+        ));
+    };
+    let text = match s.eval_expr(e, false)? {
+        SExpression::Bytes(b) => return Ok(SExpression::Bytes(b)),
+        e => e.ident(),
+    };
+
+    let b = match encoding(args.pop_front(), s)? {
+        Encoding::Latin1 => text
+            .chars()
+            .map(|c| u8::try_from(c).map_err(|_| format!("encode: {c} isn't in latin-1")))
+            .collect::<Result<Vec<u8>, String>>()?,
+        Encoding::Utf8 => text.into_bytes(),
+    };
+    Ok(SExpression::Bytes(b.into()))
+}
+
+// Run a command capturing everything about it into an association list:
+// ((stdout (...)) (stderr (...)) (status n))
+pub fn builtin_run(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let stages = s.job(e)?;
+        let out = s.exec_pipeline(stages, true, true)?;
+
+        let field = |name: &str, val: SExpression| {
+            SExpression::List(Vector::from([SExpression::Symbol(name.into()), val]))
+        };
+
+        Ok(SExpression::List(Vector::from([
+            field("stdout", lines(&out.stdout.unwrap_or_default())),
+            field("stderr", lines(&out.stderr.unwrap_or_default())),
+            field("status", SExpression::Int(out.status as i64)),
+        ])))
+    } else {
+        Err(Error::arity("run requires one argument"))
+    }
+}
+
+// Name of the signal that killed the last command, or nil if it exited normally
+pub fn builtin_last_signal(
+    _: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    Ok(match s.last_status().1 {
+        Some(sig) => SExpression::Symbol(sig.as_str().into()),
+        None => SExpression::Nil,
+    })
+}
+
+// Start a job in the background, returning its job number
+pub fn builtin_background(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        let stages = s.job(e)?;
+        let job = s.spawn_job(stages, None, None, s.new_group(false, true))?;
+        let i = s.add_job(job);
+
+        Ok(SExpression::Int(s.jobs[i].id as i64))
+    } else {
+        Err(Error::arity("& requires one argument"))
+    }
+}
+
+pub fn builtin_jobs(_: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let current = s.jobs.len().saturating_sub(1);
+
+    for (i, job) in s.jobs.iter_mut().enumerate() {
+        job.update(false);
+        println!("{}", job.describe(i == current));
     }
+    s.jobs.retain(|j| j.state != JobState::Done);
 
-    Err("read requires one argument".to_string())
+    Ok(SExpression::Nil)
+}
+
+fn job_spec(args: &mut List<SExpression>, s: &mut Interpreter) -> Result<usize, Error> {
+    let spec = match args.pop_front() {
+        Some(e) => Some(s.eval_expr(e, false)?.ident()),
+        None => None,
+    };
+
+    Ok(s.find_job(spec)?)
+}
+
+pub fn builtin_fg(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let i = job_spec(&mut args, s)?;
+    let mut job = s.jobs.remove(i);
+    eprintln!("{}", job.name);
+
+    // The job must own the terminal before it wakes up
+    s.give_terminal(job.pgid);
+    if job.state == JobState::Stopped {
+        job.signal(Signal::SIGCONT)?;
+    }
+    job.state = JobState::Running;
+    s.wait_foreground(job);
+
+    Ok(SExpression::Nil)
+}
+
+pub fn builtin_bg(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let i = job_spec(&mut args, s)?;
+    let job = &mut s.jobs[i];
+
+    job.signal(Signal::SIGCONT)?;
+    job.state = JobState::Running;
+    eprintln!("{}", job.describe(true));
+
+    Ok(SExpression::Nil)
+}
+
+// Wait for a job, or every job if none is given, to finish. $? is set to the
+// status of the last job waited for.
+pub fn builtin_wait(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let jobs = if args.is_empty() {
+        (0..s.jobs.len()).collect()
+    } else {
+        vec![job_spec(&mut args, s)?]
+    };
+
+    for i in jobs {
+        let job = &mut s.jobs[i];
+        while job.state == JobState::Running {
+            job.update(true);
+        }
+        if job.state == JobState::Done {
+            let status = job.status;
+            s.set_status(status);
+        }
+    }
+    s.jobs.retain(|j| j.state != JobState::Done);
+
+    Ok(SExpression::Nil)
+}
+
+// (kill [-SIGNAL] target...) where targets are job specs like %1 or pids
+pub fn builtin_kill(args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let mut sig = Signal::SIGTERM;
+    let mut targets = vec![];
+
+    for arg in args {
+        let arg: String = s.eval_expr(arg, false)?.ident();
+
+        if let Some(name) = arg.strip_prefix('-') {
+            sig = match name.parse::<i32>() {
+                Ok(n) => Signal::try_from(n),
+                Err(_) if name.starts_with("SIG") => name.parse(),
+                Err(_) => format!("SIG{name}").parse(),
+            }
+            .map_err(|_| format!("kill: {arg}: invalid signal"))?;
+        } else {
+            targets.push(arg);
+        }
+    }
+
+    if targets.is_empty() {
+        return Err(Error::arity("kill requires a job or pid"));
+    }
+
+    for target in targets {
+        if target.starts_with('%') {
+            let job = &s.jobs[s.find_job(Some(target))?];
+            job.signal(sig)?;
+
+            // A stopped job has to be woken up to act on the signal
+            if job.state == JobState::Stopped && sig != Signal::SIGSTOP {
+                job.signal(Signal::SIGCONT)?;
+            }
+        } else {
+            let pid = target
+                .parse()
+                .map_err(|_| format!("kill: {target}: not a pid or job"))?;
+            kill(Pid::from_raw(pid), sig).map_err(|e| format!("kill: {e}"))?;
+        }
+    }
+
+    Ok(SExpression::Nil)
+}
+
+// Get or set the maximum depth of nested evaluation, which can't be more than
+// fits on the stack
+pub fn builtin_recursion_limit(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let Some(e) = args.pop_front() {
+        s.max_depth = match s.eval_expr(e, false)?.plain() {
+            SExpression::Int(n) if n as usize > *MAX_DEPTH => {
+                return Err(format!("recursion limit can be at most {}", *MAX_DEPTH).into())
+            }
+            SExpression::Int(n) if n > 0 => n as usize,
+            e => return Err(type_error("positive int", &e)),
+        };
+    }
+
+    Ok(SExpression::Int(s.max_depth as i64))
+}
+
+// A caught error as an association list that can be read with get
+fn error_value(e: &Error, status: i32) -> SExpression {
+    let field = |name: &str, val: SExpression| {
+        SExpression::List(Vector::from([SExpression::Symbol(name.into()), val]))
+    };
+
+    SExpression::List(Vector::from([
+        field("kind", SExpression::Symbol(e.kind.name().into())),
+        field("message", SExpression::Str(e.msg.as_str().into())),
+        field("status", SExpression::Int(status as i64)),
+    ]))
+}
+
+// (try expr... (catch e handler...) (finally cleanup...)) evaluates the
+// expressions, and if one fails evaluates the handler with e bound to the
+// error. The cleanup is evaluated however the rest finished.
+pub fn builtin_try(mut args: List<SExpression>, s: &mut Interpreter) -> Result<SExpression, Error> {
+    let clause = |e: Option<&SExpression>, name: &str| e.is_some_and(|e| is_form(e, name));
+
+    let mut finally = None;
+    if clause(args.back(), "finally") {
+        if let Some(SExpression::Call(mut es, _)) = args.pop_back() {
+            es.pop_front();
+            finally = Some(es);
+        }
+    }
+
+    let mut catch = None;
+    if clause(args.back(), "catch") {
+        if let Some(SExpression::Call(mut es, _)) = args.pop_back() {
+            es.pop_front();
+            match es.pop_front() {
+                Some(SExpression::Symbol(var)) => catch = Some((var, es)),
+                _ => return Err(Error::arity("catch requires a variable and a handler")),
+            }
+        }
+    }
+
+    let mut res = eval_body(args, s);
+
+    if let (Err(e), Some((var, handler))) = (&res, catch) {
+        if e.kind != ErrorKind::Interrupt {
+            let env = s.env.clone();
+            s.env = Scope::new(Some(env.clone()));
+            let status = s.last_ret_code();
+            Scope::define(&s.env, var, error_value(e, status));
+            res = eval_body(handler, s);
+            s.env = env;
+        }
+    }
+
+    if let Some(cleanup) = finally {
+        let val = eval_body(cleanup, s)?;
+        s.finish(&val);
+    }
+
+    res
+}
+
+// Raise an error of kind user, or re-raise an error caught by try
+pub fn builtin_throw(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    let Some(e) = args.pop_front() else {
+        return Err(Error::arity("throw requires one argument"));
+    };
+
+    let e = s.eval_expr(e, false)?;
+    let field = |name: &str| match &e {
+        SExpression::List(fields) => fields.iter().find_map(|f| match f {
+            SExpression::List(f) if f.first() == Some(&SExpression::Symbol(name.into())) => {
+                f.rest().first().cloned()
+            }
+            _ => None,
+        }),
+        _ => None,
+    };
+
+    // A caught error keeps its kind
+    if let (Some(kind), Some(msg)) = (field("kind"), field("message")) {
+        if let Some(kind) = ErrorKind::from_name(&kind.ident()) {
+            return Err(Error::new(kind, msg.ident()));
+        }
+    }
+
+    Err(Error::new(ErrorKind::User, e.ident()))
+}
+
+// (error kind msg) raises an error of one of the built in kinds
+pub fn builtin_error(
+    mut args: List<SExpression>,
+    s: &mut Interpreter,
+) -> Result<SExpression, Error> {
+    if let (Some(kind), Some(msg)) = (args.pop_front(), args.pop_front()) {
+        let kind = s.eval_expr(kind, false)?.ident();
+        let msg = s.eval_expr(msg, false)?.ident();
+
+        match ErrorKind::from_name(&kind) {
+            Some(kind) => Err(Error::new(kind, msg)),
+            None => Err(Error::new(
+                ErrorKind::Type,
+                format!("unknown error kind {kind}"),
+            )),
+        }
+    } else {
+        Err(Error::arity("error requires a kind and a message"))
+    }
 }
 
 lazy_static! {
+    pub static ref SPECIAL_FORMS: HashMap<&'static str, SpecialForm> = {
+        let mut m = HashMap::new();
+
+        m.insert("if", builtin_if as SpecialForm);
+        m.insert("let", builtin_let);
+        m.insert("let*", builtin_let_star);
+        m.insert("do", builtin_do);
+        m.insert("progn", builtin_do);
+        m.insert("when", builtin_when);
+        m.insert("unless", builtin_unless);
+        m.insert("cond", builtin_cond);
+        m.insert("case", builtin_case);
+        m
+    };
     pub static ref BUILTINS: HashMap<&'static str, Func> = {
         let mut m = HashMap::new();
 
@@ -542,38 +1641,94 @@ lazy_static! {
         m.insert("-", builtin_sub);
         m.insert("*", builtin_mul);
         m.insert("/", builtin_div);
-        m.insert("%", builtin_mod);
+        m.insert("%", builtin_rem);
+        m.insert("rem", builtin_rem);
+        m.insert("quot", builtin_quot);
         m.insert("^", builtin_pow);
+        m.insert("float", builtin_float);
+        m.insert("floor", builtin_floor);
+        m.insert("round", builtin_round);
 
         m.insert("<", builtin_lt);
         m.insert(">", builtin_gt);
         m.insert("<=", builtin_leq);
         m.insert(">=", builtin_geq);
         m.insert("=", builtin_eq);
-        m.insert("if", builtin_if);
         m.insert("or", builtin_or);
         m.insert("and", builtin_and);
         m.insert("not", builtin_not);
 
+        m.insert("while", builtin_while);
+        m.insert("for", builtin_for);
+        m.insert("for-each", builtin_for_each);
+        m.insert("dotimes", builtin_dotimes);
+        m.insert("try", builtin_try);
+        m.insert("throw", builtin_throw);
+        m.insert("error", builtin_error);
+
+        m.insert("number?", builtin_is_number);
+        m.insert("int?", builtin_is_int);
+        m.insert("ratio?", builtin_is_ratio);
+        m.insert("float?", builtin_is_float);
+        m.insert("bool?", builtin_is_bool);
+        m.insert("string?", builtin_is_string);
+        m.insert("symbol?", builtin_is_symbol);
+        m.insert("list?", builtin_is_list);
+        m.insert("nil?", builtin_is_nil);
+        m.insert("function?", builtin_is_function);
+        m.insert("type-of", builtin_type_of);
+        m.insert("number", builtin_number);
+        m.insert("string", builtin_string);
+
         m.insert("first", builtin_first);
         m.insert("rest", builtin_rest);
         m.insert("list", builtin_list);
         m.insert("cons", builtin_cons);
 
         m.insert("defun", builtin_defun);
+        m.insert("lambda", builtin_lambda);
+        m.insert("defmacro", builtin_defmacro);
+        m.insert("quasiquote", builtin_quasiquote);
+        m.insert("unquote", builtin_unquote);
+        m.insert("unquote-splicing", builtin_unquote);
         m.insert("def", builtin_def);
+        m.insert("defvar", builtin_defvar);
+        m.insert("defconst", builtin_defconst);
+        m.insert("set!", builtin_set);
         m.insert("alias", builtin_alias);
-        m.insert("let", builtin_let);
 
         m.insert("export", builtin_export);
         m.insert("getenv", builtin_getenv);
 
         m.insert("cd", builtin_cd);
         m.insert("exit", builtin_exit);
+        m.insert("recursion-limit", builtin_recursion_limit);
 
         m.insert("write", builtin_file_write);
         m.insert("append", builtin_file_append);
         m.insert("read", builtin_file_read);
+
+        m.insert("|", builtin_pipe);
+        m.insert("pipe", builtin_pipe);
+        m.insert(">>", builtin_redirect_append);
+        m.insert("2>", builtin_redirect_stderr);
+        m.insert("&>", builtin_redirect_all);
+
+        m.insert("stdout", builtin_stdout);
+        m.insert("stderr", builtin_stderr);
+        m.insert("run", builtin_run);
+        m.insert("bytes", builtin_bytes);
+        m.insert("<<<", builtin_feed);
+        m.insert("decode", builtin_decode);
+        m.insert("encode", builtin_encode);
+        m.insert("last-signal", builtin_last_signal);
+
+        m.insert("&", builtin_background);
+        m.insert("jobs", builtin_jobs);
+        m.insert("fg", builtin_fg);
+        m.insert("bg", builtin_bg);
+        m.insert("wait", builtin_wait);
+        m.insert("kill", builtin_kill);
         m
     };
 }
@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::{Error, ErrorKind, SExpression, Symbol};
+
+pub type Env = Rc<RefCell<Scope>>;
+
+// A frame of variable bindings. Lookups that miss continue on to the parent
+// frame, ending at the global scope.
+pub struct Scope {
+    pub vars: HashMap<Symbol, SExpression>,
+    // variables defined with defconst, which set! refuses to change
+    pub consts: HashSet<Symbol>,
+    pub parent: Option<Env>,
+}
+
+impl Scope {
+    pub fn new(parent: Option<Env>) -> Env {
+        Rc::new(RefCell::new(Self {
+            vars: HashMap::new(),
+            consts: HashSet::new(),
+            parent,
+        }))
+    }
+
+    pub fn get(env: &Env, name: Symbol) -> Option<SExpression> {
+        let scope = env.borrow();
+
+        match scope.vars.get(&name) {
+            Some(val) => Some(val.clone()),
+            None => scope.parent.as_ref().and_then(|p| Self::get(p, name)),
+        }
+    }
+
+    pub fn define(env: &Env, name: Symbol, val: SExpression) {
+        env.borrow_mut().vars.insert(name, val);
+    }
+
+    // Define a variable unless it is a constant, which can't be redefined
+    pub fn redefine(env: &Env, name: Symbol, val: SExpression) -> Result<(), Error> {
+        Self::check_const(env, name)?;
+        Self::define(env, name, val);
+        Ok(())
+    }
+
+    pub fn check_const(env: &Env, name: Symbol) -> Result<(), Error> {
+        if env.borrow().consts.contains(&name) {
+            return Err(format!("cannot redefine constant {name}").into());
+        }
+        Ok(())
+    }
+
+    // Change the value of the nearest existing binding of a variable
+    pub fn set(env: &Env, name: Symbol, val: SExpression) -> Result<(), Error> {
+        let mut scope = env.borrow_mut();
+
+        if scope.consts.contains(&name) {
+            return Err(format!("cannot change constant {name}").into());
+        }
+
+        match scope.vars.get_mut(&name) {
+            Some(var) => {
+                *var = val;
+                Ok(())
+            }
+            None => match &scope.parent {
+                Some(p) => Self::set(p, name, val),
+                None => Err(Error::new(
+                    ErrorKind::Unbound,
+                    format!("{name} is not defined"),
+                )),
+            },
+        }
+    }
+}
+
+// A user function along with the scope it was defined in, which its body can
+// still refer to after that scope has returned
+pub struct Lambda {
+    pub name: Option<Symbol>,
+    pub params: Vec<Symbol>,
+    // collects any arguments past the named params into a list
+    pub rest: Option<Symbol>,
+    pub body: SExpression,
+    pub env: Env,
+    // macros are passed their arguments unevaluated, and what they return is
+    // evaluated in place of the call
+    pub is_macro: bool,
+}
+
+impl Lambda {
+    // Build a function from its parameter list. The last parameter may be
+    // written ...rest or . rest to accept a variable number of arguments.
+    pub fn new(
+        name: Option<Symbol>,
+        vars: SExpression,
+        body: SExpression,
+        env: Env,
+    ) -> Result<Self, String> {
+        let mut params = vars.list();
+
+        let rest = match params.len() {
+            n if n >= 2 && params[n - 2] == "." => {
+                let rest = params.pop();
+                params.pop();
+                rest
+            }
+            n if n >= 1 && params[n - 1].starts_with("...") => {
+                params.pop().map(|rest| rest[3..].to_string())
+            }
+            _ => None,
+        };
+
+        if rest.as_ref().is_some_and(|r| r.is_empty()) || params.iter().any(|p| p == ".") {
+            return Err("rest parameter must be the last parameter".to_string());
+        }
+
+        let params = params.iter().map(|p| Symbol::new(p)).collect();
+        let rest = rest.map(|r| Symbol::new(&r));
+
+        Ok(Self {
+            name,
+            params,
+            rest,
+            body,
+            env,
+            is_macro: false,
+        })
+    }
+
+    pub fn arity_error(&self, got: usize) -> String {
+        let name = match &self.name {
+            Some(name) => name.to_string(),
+            None => "lambda".to_string(),
+        };
+        let plural = if self.params.len() == 1 { "" } else { "s" };
+        let at_least = if self.rest.is_some() { "at least " } else { "" };
+
+        format!(
+            "{name} expects {at_least}{} argument{plural} but got {got}",
+            self.params.len()
+        )
+    }
+}
+
+// Functions are only ever equal to themselves
+impl PartialEq for Lambda {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Lambda {}
+
+impl std::fmt::Debug for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) if self.is_macro => write!(f, "<macro {name}>"),
+            Some(name) => write!(f, "<function {name}>"),
+            None => f.write_str("<lambda>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, SExpression};
+
+    #[test]
+    fn def_binds_a_variable_once() {
+        let mut it = Interpreter::load();
+
+        it.eval("(def n (+ 1 2))").unwrap();
+        it.eval("(set! n (+ n 1))").unwrap();
+        assert_eq!(it.eval("(do n)").unwrap(), SExpression::Int(4));
+    }
+
+    #[test]
+    fn constants_cannot_be_redefined() {
+        let mut it = Interpreter::load();
+
+        it.eval("(defconst editor vim)").unwrap();
+        assert!(it.eval("(def editor emacs)").is_err());
+        assert!(it.eval("(defvar editor emacs)").is_err());
+        assert!(it.eval("(defun editor () emacs)").is_err());
+        assert!(it.eval("(set! editor emacs)").is_err());
+        assert_eq!(it.eval("(do editor)").unwrap().ident(), "vim");
+    }
+}
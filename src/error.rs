@@ -0,0 +1,274 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+// Text that expressions were parsed from, kept so errors can quote it
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+// A range of characters in a source
+#[derive(Clone, Default)]
+pub struct Span {
+    pub source: Option<Rc<Source>>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(source: &Rc<Source>, start: usize, end: usize) -> Self {
+        Self {
+            source: Some(source.clone()),
+            start,
+            end,
+        }
+    }
+
+    // Line and column of the start of the span, counting from 1
+    fn position(&self, text: &str) -> (usize, usize) {
+        let before: String = text.chars().take(self.start).collect();
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+        (line, col)
+    }
+
+    // Where the span is, as name:line:col
+    pub fn location(&self) -> Option<String> {
+        let source = self.source.as_ref()?;
+        let (line, col) = self.position(&source.text);
+
+        Some(format!("{}:{line}:{col}", source.name))
+    }
+}
+
+// Where an expression came from doesn't change what it is
+impl PartialEq for Span {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Parse,
+    Type,
+    Arity,
+    Unbound,
+    CommandNotFound,
+    Io,
+    User,
+    Runtime,
+    // Ctrl-C, which try doesn't catch
+    Interrupt,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Type => "type",
+            Self::Arity => "arity",
+            Self::Unbound => "unbound",
+            Self::CommandNotFound => "command-not-found",
+            Self::Io => "io",
+            Self::User => "user",
+            Self::Runtime => "runtime",
+            Self::Interrupt => "interrupt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Parse,
+            Self::Type,
+            Self::Arity,
+            Self::Unbound,
+            Self::CommandNotFound,
+            Self::Io,
+            Self::User,
+            Self::Runtime,
+            Self::Interrupt,
+        ]
+        .into_iter()
+        .find(|k| k.name() == name)
+    }
+}
+
+// The most calls an error shows on its way out, counting a run of the same
+// call as one
+const MAX_CALLS: usize = 20;
+
+// A call to a user function that an error unwound through
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub msg: String,
+    // the expression being evaluated when the error happened
+    pub span: Option<Span>,
+    // user function calls the error passed through, innermost first
+    pub trace: Vec<Frame>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, msg: impl Into<String>) -> Self {
+        Self {
+            kind,
+            msg: msg.into(),
+            span: None,
+            trace: vec![],
+        }
+    }
+
+    pub fn parse(msg: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Parse, msg).at(&span)
+    }
+
+    pub fn arity(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Arity, msg)
+    }
+
+    pub fn io(msg: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Io, msg)
+    }
+
+    // Attach a location, unless the error already points somewhere more
+    // specific
+    pub fn at(mut self, span: &Span) -> Self {
+        if self.span.is_none() && span.source.is_some() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+
+    // Render the error with the source it points at underlined, followed by
+    // the user function calls it unwound through
+    pub fn render(&self) -> String {
+        let mut out = format!("error[{}]: {}\n", self.kind.name(), self.msg);
+
+        if let Some(span) = &self.span {
+            if let (Some(source), Some(location)) = (&span.source, span.location()) {
+                let (line, col) = span.position(&source.text);
+                let text = source.text.lines().nth(line - 1).unwrap_or_default();
+                let len = text.chars().count();
+
+                let width = (span.end - span.start).clamp(1, (len + 1).saturating_sub(col).max(1));
+                let gutter = " ".repeat(line.to_string().len());
+
+                let _ = writeln!(out, "{gutter}--> {location}");
+                let _ = writeln!(out, "{gutter} |");
+                let _ = writeln!(out, "{line} | {text}");
+                let _ = writeln!(
+                    out,
+                    "{gutter} | {}{}",
+                    " ".repeat(col - 1),
+                    "^".repeat(width)
+                );
+            }
+        }
+
+        // Runs of the same call, as in deep recursion, are shown once with a
+        // count, and a long trace only shows its innermost and outermost calls
+        let mut calls: Vec<(String, usize)> = vec![];
+        for frame in &self.trace {
+            let call = match frame.span.location() {
+                Some(location) => format!("in {} called at {location}", frame.name),
+                None => format!("in {}", frame.name),
+            };
+            match calls.last_mut() {
+                Some((last, n)) if *last == call => *n += 1,
+                _ => calls.push((call, 1)),
+            }
+        }
+
+        let (inner, outer, hidden) = if calls.len() > MAX_CALLS {
+            let (inner, rest) = calls.split_at(MAX_CALLS / 2);
+            let (hidden, outer) = rest.split_at(rest.len() - MAX_CALLS / 2);
+            (inner, outer, hidden.iter().map(|(_, n)| n).sum())
+        } else {
+            (&calls[..], &[][..], 0)
+        };
+
+        for (call, n) in inner {
+            write_call(&mut out, call, *n);
+        }
+        if hidden > 0 {
+            let _ = writeln!(out, "  = ... {hidden} more calls");
+        }
+        for (call, n) in outer {
+            write_call(&mut out, call, *n);
+        }
+
+        out.trim_end().to_string()
+    }
+}
+
+fn write_call(out: &mut String, call: &str, n: usize) {
+    let _ = match n {
+        1 => writeln!(out, "  = {call}"),
+        n => writeln!(out, "  = {call} ({n} times)"),
+    };
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Self::new(ErrorKind::Runtime, msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Self {
+        Self::new(ErrorKind::Runtime, msg)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+
+    #[test]
+    fn repeated_calls_are_shown_once() {
+        let mut it = Interpreter::load();
+
+        let e = it
+            .eval("(do (defun f (n) (if (= n 0) (first 1) (+ 1 (f (- n 1))))) (f 100))")
+            .unwrap_err()
+            .render();
+        assert!(e.contains("(100 times)"), "{e}");
+        assert_eq!(e.lines().filter(|l| l.starts_with("  =")).count(), 2, "{e}");
+    }
+
+    #[test]
+    fn long_traces_are_cut_short() {
+        let mut it = Interpreter::load();
+
+        it.eval("(defun f (n) (if (= n 0) (first 1) (+ 1 (g (- n 1)))))")
+            .unwrap();
+        it.eval("(defun g (n) (+ 1 (f n)))").unwrap();
+        let e = it.eval("(f 50)").unwrap_err().render();
+        assert!(e.contains("= ... 81 more calls"), "{e}");
+        assert_eq!(
+            e.lines().filter(|l| l.starts_with("  =")).count(),
+            21,
+            "{e}"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::env::var;
+
+use glob::{MatchOptions, Pattern};
+use nix::unistd::User;
+
+use crate::{Error, SExpression};
+
+// The words an evaluated command argument stands for. Bare words that weren't
+// variables are expanded, while strings and other values are passed as they
+// are.
+pub fn expand_value(e: SExpression) -> Vec<String> {
+    match e {
+        SExpression::Symbol(s) => expand(&s.name()),
+        e => vec![e.ident()],
+    }
+}
+
+// Expand a value that has to stay a single word, such as a file to redirect to
+pub fn expand_one(e: SExpression) -> Result<String, Error> {
+    match expand_value(e).as_slice() {
+        [w] => Ok(w.clone()),
+        ws => Err(format!("{}: ambiguous file name", ws.join(" ")).into()),
+    }
+}
+
+// Expand a word from a command line the way a shell would: braces first, then
+// a leading ~, then wildcards. A pattern that matches no files is kept as it
+// is.
+pub fn expand(word: &str) -> Vec<String> {
+    braces(word)
+        .into_iter()
+        .map(|w| tilde(&w))
+        .flat_map(|w| wildcards(&w))
+        .collect()
+}
+
+// a{b,c}d becomes abd and acd. Braces without a comma at their top level are
+// left alone.
+fn braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+
+    for (open, _) in chars.iter().enumerate().filter(|(_, c)| **c == '{') {
+        let mut depth = 0;
+        let mut commas = vec![];
+
+        for (i, c) in chars.iter().enumerate().skip(open) {
+            match c {
+                '{' => depth += 1,
+                ',' if depth == 1 => commas.push(i),
+                '}' if depth == 1 => {
+                    if commas.is_empty() {
+                        break;
+                    }
+
+                    let text = |a: usize, b: usize| chars[a..b].iter().collect::<String>();
+                    let prefix = text(0, open);
+                    let suffix = text(i + 1, chars.len());
+
+                    let bounds = std::iter::once(open).chain(commas).chain([i]);
+                    let bounds: Vec<usize> = bounds.collect();
+
+                    return bounds
+                        .windows(2)
+                        .flat_map(|w| braces(&format!("{prefix}{}{suffix}", text(w[0] + 1, w[1]))))
+                        .collect();
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    vec![word.to_string()]
+}
+
+// ~ and ~/dir are relative to $HOME, and ~user to that user's home directory
+fn tilde(word: &str) -> String {
+    let Some(rest) = word.strip_prefix('~') else {
+        return word.to_string();
+    };
+    let (user, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+    let home = if user.is_empty() {
+        var("HOME").ok()
+    } else {
+        User::from_name(user)
+            .ok()
+            .flatten()
+            .map(|u| u.dir.to_string_lossy().to_string())
+    };
+
+    match home {
+        Some(home) => format!("{home}{path}"),
+        None => word.to_string(),
+    }
+}
+
+// Match *, ?, [..] and ** against files. Hidden files are only matched by a
+// pattern that starts with a dot.
+fn wildcards(word: &str) -> Vec<String> {
+    if !word.contains(['*', '?', '[']) {
+        return vec![word.to_string()];
+    }
+
+    // The glob crate skips every hidden file when asked to require a literal
+    // dot, so list them all and check the leading dots against the pattern.
+    // That also lists . and .., which are only kept when written out. It
+    // leaves off a leading ./, which is put back afterwards.
+    let hidden = MatchOptions {
+        require_literal_leading_dot: true,
+        ..MatchOptions::new()
+    };
+    let (rel, dir) = match word.strip_prefix("./") {
+        Some(rel) => (rel, "./"),
+        None => (word, ""),
+    };
+    let (Ok(paths), Ok(pattern)) = (glob::glob(word), Pattern::new(rel)) else {
+        return vec![word.to_string()];
+    };
+    let written = |p: &str| {
+        p.split('/')
+            .zip(rel.split('/'))
+            .all(|(w, pat)| !matches!(w, "." | "..") || w == pat)
+    };
+
+    let matches: Vec<String> = paths
+        .flatten()
+        .map(|p| p.to_string_lossy().to_string())
+        .map(|p| p.strip_prefix("./").map(str::to_string).unwrap_or(p))
+        .filter(|p| pattern.matches_with(p, hidden) && written(p))
+        .map(|p| format!("{dir}{p}"))
+        .collect();
+
+    if matches.is_empty() {
+        vec![word.to_string()]
+    } else {
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::var;
+    use std::fs;
+
+    use super::{braces, expand, tilde};
+
+    #[test]
+    fn braces_expand_in_order_and_nest() {
+        assert_eq!(braces("a{b,c}d"), ["abd", "acd"]);
+        assert_eq!(braces("{a,b{1,2}}"), ["a", "b1", "b2"]);
+        assert_eq!(braces("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(braces("x{,y}"), ["x", "xy"]);
+        assert_eq!(braces("{a}"), ["{a}"]);
+        assert_eq!(braces("{}"), ["{}"]);
+    }
+
+    #[test]
+    fn tilde_is_the_home_directory() {
+        let home = var("HOME").unwrap();
+
+        assert_eq!(tilde("~"), home);
+        assert_eq!(tilde("~/src"), format!("{home}/src"));
+        assert_eq!(tilde("a~b"), "a~b");
+        assert_eq!(tilde("~no-such-user-here/x"), "~no-such-user-here/x");
+    }
+
+    #[test]
+    fn wildcards_match_files_but_not_hidden_ones() {
+        let dir = std::env::temp_dir().join(format!("lishp-glob-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for f in ["a.txt", "b.txt", "c.rs", ".hidden.txt", "sub/d.txt"] {
+            fs::write(dir.join(f), "").unwrap();
+        }
+        let d = dir.to_str().unwrap();
+
+        assert_eq!(
+            expand(&format!("{d}/*.txt")),
+            [format!("{d}/a.txt"), format!("{d}/b.txt")]
+        );
+        assert_eq!(expand(&format!("{d}/.*.txt")), [format!("{d}/.hidden.txt")]);
+        assert_eq!(expand(&format!("{d}/?.rs")), [format!("{d}/c.rs")]);
+        assert_eq!(expand(&format!("{d}/.*")), [format!("{d}/.hidden.txt")]);
+        assert_eq!(
+            expand(&format!("{d}/sub/../?.rs")),
+            [format!("{d}/sub/../c.rs")]
+        );
+        assert_eq!(expand(&format!("{d}/**/d.txt")), [format!("{d}/sub/d.txt")]);
+        assert_eq!(
+            expand(&format!("{d}/{{a,c}}.*")),
+            [format!("{d}/a.txt"), format!("{d}/c.rs")]
+        );
+        // A pattern that matches nothing is passed on as it is
+        assert_eq!(expand(&format!("{d}/*.md")), [format!("{d}/*.md")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -19,6 +19,12 @@ pub enum History {
 
 pub struct Input {}
 
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Input {
     pub fn new() -> Self {
         Input {}
@@ -74,13 +80,13 @@ impl Input {
             execute!(stdout, MoveTo(start_col + cursor, start_row)).unwrap();
 
             // Read and process the next key
-            match read().unwrap() {
-                Event::Key(KeyEvent { code, modifiers }) => match (code, modifiers) {
+            if let Event::Key(KeyEvent { code, modifiers }) = read().unwrap() {
+                match (code, modifiers) {
                     // autocomplete
                     (KeyCode::Tab, _) => {
                         let cs = complete(&buf, cursor as usize);
 
-                        if cs.len() == 0 {
+                        if cs.is_empty() {
                             continue;
                         }
 
@@ -111,7 +117,7 @@ impl Input {
 
                         if cs.len() > 1 && cs.len() < 25 {
                             disable_raw_mode().unwrap();
-                            write!(stdout, "\n").unwrap();
+                            writeln!(stdout).unwrap();
                             println!(
                                 "{}",
                                 cs.into_iter()
@@ -138,12 +144,12 @@ impl Input {
                     // Control characters
                     (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
                         disable_raw_mode().unwrap();
-                        write!(stdout, "\n").unwrap();
+                        writeln!(stdout).unwrap();
                         return Err("".to_string());
                     }
                     // Navigation
                     (KeyCode::Left, _) | (KeyCode::Char('b'), KeyModifiers::CONTROL) => {
-                        cursor = 0.max(cursor - 1)
+                        cursor = cursor.saturating_sub(1)
                     }
                     (KeyCode::Right, _) | (KeyCode::Char('f'), KeyModifiers::CONTROL) => {
                         cursor = (buf.len() as u16).min(cursor + 1)
@@ -170,11 +176,9 @@ impl Input {
                         }
                     },
                     // Editing
-                    (KeyCode::Backspace, _) => {
-                        if buf.len() > 0 && cursor > 0 {
-                            buf.remove(cursor as usize - 1);
-                            cursor -= 1;
-                        }
+                    (KeyCode::Backspace, _) if !buf.is_empty() && cursor > 0 => {
+                        buf.remove(cursor as usize - 1);
+                        cursor -= 1;
                     }
                     (KeyCode::Char('('), _) => {
                         // Count number of ( and )
@@ -204,12 +208,11 @@ impl Input {
                     }
                     (KeyCode::Enter, _) => {
                         disable_raw_mode().unwrap();
-                        write!(stdout, "\n").unwrap();
+                        writeln!(stdout).unwrap();
                         break;
                     }
                     _ => {}
-                },
-                _ => {}
+                }
             }
         }
 
@@ -238,7 +241,7 @@ fn highlight_parens(buf: &str, cursor: usize) -> Vec<usize> {
     if let Some(i) = stack.pop_front() {
         hls.push(i);
 
-        for (i, c) in buf.chars().enumerate().skip(cursor as usize) {
+        for (i, c) in buf.chars().enumerate().skip(cursor) {
             if c == ')' {
                 hls.push(i);
                 break;
@@ -1,203 +1,350 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::collections::LinkedList as List;
+use std::collections::VecDeque as List;
 use std::env::var;
 use std::ffi::CString;
 use std::fs::read_dir;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use crate::parser::{parse_file, parse_str};
 
-use crate::builtins::BUILTINS;
-use crate::SExpression;
-
-use nix::libc;
-use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::{close, dup2, execv, fork, pipe, read, ForkResult};
+use crate::builtins::{Tail, BUILTINS, SPECIAL_FORMS};
+use crate::env::{Env, Lambda, Scope};
+use crate::error::{Error, ErrorKind, Frame};
+use crate::{SExpression, Symbol};
+
+use crate::jobs::{interrupted, Job, Status};
+
+use lazy_static::lazy_static;
+use nix::unistd::{getpgrp, Pid};
+
+// Evaluation recurses on the Rust stack, so it runs on a thread with a stack
+// of STACK_SIZE, 8 MiB unless $LISHP_STACK_SIZE gives another number of MiB.
+// Each nested evaluation takes up to about FRAME_SIZE of it, as measured with
+// non-tail recursion through user functions, which is much more without
+// optimizations. The deepest nesting allowed is what fits.
+pub const FRAME_SIZE: usize = if cfg!(debug_assertions) {
+    12 << 10
+} else {
+    3 << 10
+};
+
+lazy_static! {
+    pub static ref STACK_SIZE: usize = var("LISHP_STACK_SIZE")
+        .ok()
+        .and_then(|mib| mib.parse::<usize>().ok())
+        .and_then(|mib| mib.checked_mul(1 << 20))
+        .filter(|size| *size > 0)
+        .unwrap_or(8 << 20);
+    pub static ref MAX_DEPTH: usize = *STACK_SIZE / FRAME_SIZE;
+}
 
 pub struct Interpreter {
     // lower level aliases for preprocessing the input text
-    pub aliases: HashMap<List<char>, List<List<char>>>,
-    // User definitions, literally just use as tree substitutions
-    pub defs: HashMap<List<char>, SExpression>,
-    // User defined functions, have name, list of args, and then the substituted tree
-    pub funcs: HashMap<List<char>, (Vec<List<char>>, SExpression)>,
+    pub aliases: HashMap<String, Vec<String>>,
+
+    // Top level scope holding user defined functions
+    pub globals: Env,
+    // Scope that the expression being evaluated sees
+    pub env: Env,
+
+    // exit code and signal of the last command, for $?
+    pub status: Rc<RefCell<Status>>,
+
+    // background and stopped jobs
+    pub jobs: Vec<Job>,
 
-    // last unique id of a given variable
-    pub id: usize,
+    // whether the shell controls the terminal and runs jobs in their own
+    // process groups
+    pub job_control: bool,
+    pub shell_pgid: Pid,
 
-    // last return code
-    pub last_ret_code: i32,
+    // how deeply evaluation is currently nested, and the limit on it
+    pub depth: usize,
+    pub max_depth: usize,
+
+    // whether the currently running builtin was called at the root of the
+    // expression, where command output goes straight to the terminal
+    pub root: bool,
 }
 
 impl Interpreter {
     pub fn load() -> Self {
+        let globals = Scope::new(None);
+
         let mut me = Self {
-            id: 0,
             aliases: HashMap::new(),
-            defs: HashMap::new(),
-            funcs: HashMap::new(),
-            last_ret_code: 0,
+            globals: globals.clone(),
+            env: globals,
+            status: Rc::default(),
+            jobs: Vec::new(),
+            job_control: false,
+            shell_pgid: getpgrp(),
+            depth: 0,
+            max_depth: *MAX_DEPTH,
+            root: false,
         };
 
         // Load prelude
-        for expr in parse_str(include_str!("prelude.lisp")) {
+        for expr in parse_str(
+            "prelude.lisp",
+            include_str!("prelude.lisp"),
+            &HashMap::new(),
+        )
+        .unwrap()
+        {
             me.eval_expr(expr, false).unwrap();
         }
 
-        // Load .lishprc
-        for expr in parse_file("/home/devin/.lishprc") {
-            me.eval_expr(expr, false).unwrap();
+        me
+    }
+
+    // Where the rc file is: $LISHP_RC if it is set, otherwise the first of
+    // $XDG_CONFIG_HOME/lishp/init.lisp (or ~/.config/lishp/init.lisp) and
+    // ~/.lishprc that exists
+    pub fn rc_path() -> Option<PathBuf> {
+        if let Ok(rc) = var("LISHP_RC") {
+            return Some(rc.into());
         }
 
-        me
+        let home = var("HOME").ok().map(PathBuf::from);
+        let config = match var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => home.as_ref().map(|h| h.join(".config")),
+        };
+
+        [
+            config.map(|c| c.join("lishp").join("init.lisp")),
+            home.map(|h| h.join(".lishprc")),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|p| p.exists())
+    }
+
+    // Run an rc file, reporting any error in it rather than stopping
+    pub fn load_rc(&mut self, path: &Path) {
+        if let Err(e) = self.run_file(&path.to_string_lossy()) {
+            eprintln!("{}", e.render());
+        }
+    }
+
+    // Make the arguments a script was run with available as *args* and as
+    // $0 for the script itself, then $1 to $n
+    pub fn set_args(&mut self, script: &str, args: Vec<String>) {
+        Scope::define(&self.globals, "$0".into(), SExpression::Str(script.into()));
+        for (i, arg) in args.iter().enumerate() {
+            let name = Symbol::new(&format!("${}", i + 1));
+            Scope::define(&self.globals, name, SExpression::Str(arg.as_str().into()));
+        }
+
+        let args = args
+            .into_iter()
+            .map(|a| SExpression::Str(a.into()))
+            .collect();
+        Scope::define(&self.globals, "*args*".into(), SExpression::List(args));
+    }
+
+    // Run every expression in a file as if it were typed at the prompt,
+    // stopping at the first error
+    pub fn run_file(&mut self, path: &str) -> Result<(), Error> {
+        for expr in parse_file(path)? {
+            self.eval_expr(expr, true)?;
+        }
+
+        Ok(())
     }
 
-    pub fn eval(&mut self, cmd: &str) -> Result<SExpression, String> {
+    pub fn eval(&mut self, cmd: &str) -> Result<SExpression, Error> {
+        // Forget about any ctrl-c from before this command
+        interrupted();
+
         // Parse Expression
-        let expr = SExpression::parse(&cmd, &self.aliases)?;
+        let expr = SExpression::parse(cmd, &self.aliases)?;
         // Evaluate Expression
         self.eval_expr(expr, true)
     }
 
-    pub fn eval_expr(&mut self, e: SExpression, root: bool) -> Result<SExpression, String> {
-        match e {
-            SExpression::Call(mut es) => {
-                let func = es
-                    .pop_front()
-                    .ok_or("Empty Call Expression".to_string())?
-                    .ident();
-                let func_name: String = func.iter().collect();
-                let args = es;
-
-                // If func is a builtin method, run it and print result.
-                if let Some(f) = BUILTINS.get(func_name.as_str()) {
-                    return f(args, self);
-                }
+    // Evaluate every expression in some text, returning the value of the last
+    pub fn eval_all(&mut self, name: &str, text: &str) -> Result<SExpression, Error> {
+        interrupted();
 
-                // If func is in user defined functions then run the subs
-                if self.funcs.get(&func).is_some() {
-                    let mut fargs = List::new();
+        let mut val = SExpression::Nil;
+        for expr in parse_str(name, text, &self.aliases)? {
+            val = self.eval_expr(expr, true)?;
+        }
 
-                    for arg in args.into_iter() {
-                        fargs.push_back(self.eval_expr(arg, false)?);
-                    }
+        Ok(val)
+    }
 
-                    if let Some((vars, tree)) = self.funcs.get(&func) {
-                        let tree = vars
-                            .iter()
-                            .zip(fargs.iter())
-                            .fold(tree.clone(), |t, (var, sub)| t.replace(var, sub.clone()));
-                        return self.eval_expr(tree, false);
-                    } else {
-                        unreachable!()
+    pub fn eval_expr(&mut self, e: SExpression, root: bool) -> Result<SExpression, Error> {
+        if self.depth >= self.max_depth {
+            return Err(format!("maximum recursion depth of {} exceeded", self.max_depth).into());
+        }
+
+        // Tail calls replace the current scope, so put it back afterwards
+        let env = self.env.clone();
+        self.depth += 1;
+        let mut frame = None;
+        let res = self.eval_tail(e, root, &mut frame);
+        self.depth -= 1;
+        self.env = env;
+
+        // Errors collect the user function calls they unwind through
+        match (res, frame) {
+            (Err(mut err), Some(frame)) => {
+                err.trace.push(frame);
+                Err(err)
+            }
+            (res, _) => res,
+        }
+    }
+
+    // Evaluate an expression, looping on whatever is in tail position rather
+    // than recursing into it. frame is set to the user function call that is
+    // being evaluated, if any.
+    fn eval_tail(
+        &mut self,
+        mut e: SExpression,
+        mut root: bool,
+        frame: &mut Option<Frame>,
+    ) -> Result<SExpression, Error> {
+        loop {
+            if interrupted() {
+                return Err(Error::new(ErrorKind::Interrupt, "interrupted"));
+            }
+
+            match e {
+                SExpression::Call(mut es, span) => {
+                    let head = es
+                        .pop_front()
+                        .ok_or_else(|| Error::from("Empty Call Expression").at(&span))?;
+                    let args = es;
+
+                    // The head of a call can be a variable holding a function or
+                    // the name of a command, or an expression evaluating to one
+                    let head = match head {
+                        SExpression::Symbol(name) => {
+                            Scope::get(&self.env, name).unwrap_or(SExpression::Symbol(name))
+                        }
+                        SExpression::Call(..) => self.eval_expr(head, false)?,
+                        head => head,
+                    };
+
+                    // A macro builds code from its unevaluated arguments, which
+                    // is then evaluated in place of the call
+                    if let SExpression::Function(f) = &head {
+                        if f.is_macro {
+                            let scope = self.bind(f, args).map_err(|e| e.at(&span))?;
+                            let env = std::mem::replace(&mut self.env, scope);
+                            let expansion = self.eval_expr(f.body.clone(), false);
+                            self.env = env;
+
+                            e = match expansion.map_err(|e| e.at(&span))? {
+                                SExpression::List(es) => {
+                                    SExpression::Call(es.into_iter().collect(), span)
+                                }
+                                e => e,
+                            };
+                            continue;
+                        }
                     }
-                }
 
-                let (fd_read, fd_write) = pipe().unwrap();
+                    if let SExpression::Function(f) = head {
+                        let mut fargs = List::new();
 
-                // Else search path for binary, fork, and exec it with args
-                let bin = self.search_path(&func_name);
-                if bin.is_none() {
-                    return Err(format!("command not found: {}", func_name));
-                }
+                        for arg in args.into_iter() {
+                            fargs.push_back(self.eval_expr(arg, false)?);
+                        }
 
-                let bin = bin.unwrap();
-
-                let mut fargs = vec![];
-                fargs.push(CString::new(func_name).unwrap());
-
-                for arg in args {
-                    fargs.push(
-                        CString::new(
-                            self.eval_expr(arg, false)?
-                                .ident()
-                                .iter()
-                                .collect::<String>(),
-                        )
-                        .unwrap(),
-                    )
-                }
-                let args = fargs;
-
-                // Fork, Exec
-                match unsafe { fork() } {
-                    Ok(ForkResult::Parent { child, .. }) => {
-                        let mut out = String::new();
-                        let mut buf = [0; 1024];
-
-                        close(fd_write).unwrap();
-
-                        if !root {
-                            while let Ok(n) = read(fd_read, &mut buf) {
-                                if n == 0 {
-                                    break;
-                                } // EOF
-                                out.push_str(&String::from_utf8_lossy(&buf[0..n]));
-                            }
+                        self.env = self.bind(&f, fargs).map_err(|e| e.at(&span))?;
+                        *frame = Some(Frame {
+                            name: f.name.map_or("lambda".to_string(), |n| n.to_string()),
+                            span,
+                        });
+                        e = f.body.clone();
+                        root = false;
+                        continue;
+                    }
 
-                            loop {
-                                let status = waitpid(child, Some(WaitPidFlag::WUNTRACED)).unwrap();
-                                match status {
-                                    WaitStatus::Exited(_, _) => {
-                                        break;
-                                    }
-                                    WaitStatus::Signaled(_, _, _) => break,
-                                    _ => continue,
-                                }
-                            }
+                    let func_name = head.ident();
 
-                            let lines = out
-                                .lines()
-                                .map(|s| SExpression::Atom(s.chars().collect()))
-                                .collect();
-
-                            Ok(SExpression::List(lines))
-                        } else {
-                            loop {
-                                if let Ok(status) = waitpid(child, Some(WaitPidFlag::WUNTRACED)) {
-                                    match status {
-                                        WaitStatus::Exited(_, exit) => {
-                                            self.last_ret_code = exit;
-                                            break;
-                                        }
-                                        WaitStatus::Signaled(_, _, _) => break,
-                                        _ => continue,
-                                    }
-                                }
+                    if let Some(f) = SPECIAL_FORMS.get(func_name.as_str()) {
+                        let prev = std::mem::replace(&mut self.root, root);
+                        let res = f(args, self);
+                        self.root = prev;
+
+                        match res.map_err(|e| e.at(&span))? {
+                            Tail::Value(v) => return Ok(v),
+                            Tail::Eval(next) => {
+                                e = next;
+                                continue;
                             }
-                            Ok(SExpression::Atom(List::new()))
                         }
                     }
-                    Ok(ForkResult::Child) => {
-                        if !root {
-                            dup2(fd_write, 1).unwrap();
-                        }
-                        close(fd_write).unwrap();
-                        close(fd_read).unwrap();
 
-                        if let Err(e) = execv(&bin, &args) {
-                            eprintln!("error: {e}");
-                        }
-                        unsafe { libc::_exit(0) }
+                    // If func is a builtin method, run it and print result.
+                    if let Some(f) = BUILTINS.get(func_name.as_str()) {
+                        let prev = std::mem::replace(&mut self.root, root);
+                        let res = f(args, self);
+                        self.root = prev;
+                        return res.map_err(|e| e.at(&span));
                     }
-                    _ => panic!("ah"),
+
+                    // Else search path for binary, fork, and exec it with args
+                    return self
+                        .command(func_name, args)
+                        .and_then(|cmd| self.run_pipeline(vec![cmd], root))
+                        .map_err(|e| e.at(&span));
                 }
-            }
-            SExpression::Atom(s) => {
-                if s == "$?".chars().collect() {
-                    Ok(SExpression::Atom(
-                        self.last_ret_code.to_string().chars().collect(),
-                    ))
-                } else if let Some(expr) = self.defs.get(&s) {
-                    Ok(expr.clone())
-                } else {
-                    Ok(SExpression::Atom(s))
+                SExpression::Symbol(s) => {
+                    return if s == Symbol::new("$?") {
+                        Ok(SExpression::Int(self.last_ret_code() as i64))
+                    } else if let Some(val) = Scope::get(&self.env, s) {
+                        Ok(val)
+                    } else {
+                        Ok(SExpression::Symbol(s))
+                    };
                 }
+                a => return Ok(a),
             }
-            a => Ok(a),
         }
     }
 
+    // Create the scope for a call to a user function, binding its parameters
+    // to the already evaluated arguments
+    pub fn bind(&self, f: &Lambda, mut args: List<SExpression>) -> Result<Env, Error> {
+        let n = f.params.len();
+        if args.len() < n || (args.len() > n && f.rest.is_none()) {
+            return Err(Error::arity(f.arity_error(args.len())));
+        }
+
+        let scope = Scope::new(Some(f.env.clone()));
+        let rest = args.split_off(n);
+
+        for (param, arg) in f.params.iter().zip(args) {
+            Scope::define(&scope, *param, arg);
+        }
+        if let Some(name) = &f.rest {
+            Scope::define(&scope, *name, SExpression::List(rest.into_iter().collect()));
+        }
+
+        Ok(scope)
+    }
+
+    // Call a user function with already evaluated arguments. The body runs in
+    // a new scope inside the one the function was defined in.
+    pub fn apply(&mut self, f: Rc<Lambda>, args: List<SExpression>) -> Result<SExpression, Error> {
+        let scope = self.bind(&f, args)?;
+
+        let prev = std::mem::replace(&mut self.env, scope);
+        let res = self.eval_expr(f.body.clone(), false);
+        self.env = prev;
+
+        res
+    }
+
     pub fn search_path(&self, s: &str) -> Option<CString> {
         let path: Vec<_> = var("PATH")
             .expect("Failed to read PATH")
@@ -215,23 +362,19 @@ impl Interpreter {
 
         for dir in path.iter() {
             if let Ok(entries) = read_dir(dir) {
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        if entry.file_name() == s {
-                            let p = entry.path();
-                            return Some(CString::new(p.to_str().unwrap()).unwrap());
-                        }
-                    }
-                }
-            }
-            for entry in read_dir(dir).ok()? {
-                if let Ok(entry) = entry {
+                for entry in entries.flatten() {
                     if entry.file_name() == s {
                         let p = entry.path();
                         return Some(CString::new(p.to_str().unwrap()).unwrap());
                     }
                 }
             }
+            for entry in read_dir(dir).ok()?.flatten() {
+                if entry.file_name() == s {
+                    let p = entry.path();
+                    return Some(CString::new(p.to_str().unwrap()).unwrap());
+                }
+            }
         }
 
         None
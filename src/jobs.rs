@@ -0,0 +1,307 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use crate::Interpreter;
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+
+// Set when the shell receives SIGINT, and checked by the evaluator so that
+// runaway evaluation can be abandoned
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Process group of the job running in the foreground, or 0 if there is none
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+// Install a SIGINT handler that interrupts evaluation and passes the signal on
+// to the foreground job. Jobs that share the shell's process group already get
+// it from the terminal.
+pub fn handle_interrupts() {
+    ctrlc::set_handler(|| {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+
+        let pgid = FOREGROUND.load(Ordering::SeqCst);
+        if pgid != 0 {
+            let _ = kill(Pid::from_raw(-pgid), Signal::SIGINT);
+        }
+    })
+    .unwrap();
+}
+
+// Check for and clear a pending interrupt
+pub fn interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+// The status of the last command to finish, for $?. It is shared with the
+// streams reading command output, which set it when their command finishes.
+// Commands are numbered as they start, and a status only replaces one from a
+// command that started earlier, so that output read late doesn't hide the
+// status of the commands run since.
+#[derive(Default)]
+pub struct Status {
+    pub code: i32,
+    pub signal: Option<Signal>,
+    // the command the status is from, and the last command started
+    from: u64,
+    started: u64,
+}
+
+impl Status {
+    // Number a command that is starting
+    pub fn start(&mut self) -> u64 {
+        self.started += 1;
+        self.started
+    }
+
+    // Record the status of a command unless a later one has already finished
+    pub fn set(&mut self, cmd: u64, (code, signal): (i32, Option<Signal>)) {
+        if cmd >= self.from {
+            self.from = cmd;
+            self.code = code;
+            self.signal = signal;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub done: bool,
+}
+
+// A pipeline of processes started by the shell. With job control enabled all
+// of the processes share a process group so they can be signalled together and
+// moved between the foreground and background.
+pub struct Job {
+    pub id: usize,
+    pub pgid: Option<Pid>,
+    pub procs: Vec<Process>,
+    pub name: String,
+    pub state: JobState,
+
+    // exit code and terminating signal of the last process in the pipeline
+    pub status: (i32, Option<Signal>),
+}
+
+impl Job {
+    pub fn new(pgid: Option<Pid>, pids: Vec<Pid>, name: String) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            procs: pids
+                .into_iter()
+                .map(|pid| Process { pid, done: false })
+                .collect(),
+            name,
+            state: JobState::Running,
+            status: (0, None),
+        }
+    }
+
+    // Collect status changes of the job's processes. A blocking update waits
+    // until every process has exited or one of them has stopped.
+    pub fn update(&mut self, block: bool) {
+        let mut flags = WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        if !block {
+            flags |= WaitPidFlag::WNOHANG;
+        }
+
+        let last = self.procs.len().saturating_sub(1);
+
+        for i in 0..self.procs.len() {
+            while !self.procs[i].done {
+                match waitpid(self.procs[i].pid, Some(flags)) {
+                    Ok(WaitStatus::Exited(_, code)) => {
+                        self.procs[i].done = true;
+                        if i == last {
+                            self.status = (code, None);
+                        }
+                    }
+                    Ok(WaitStatus::Signaled(_, sig, _)) => {
+                        self.procs[i].done = true;
+                        if i == last {
+                            self.status = (128 + sig as i32, Some(sig));
+                        }
+                    }
+                    Ok(WaitStatus::Stopped(_, _)) => {
+                        self.state = JobState::Stopped;
+                        if block {
+                            return;
+                        }
+                    }
+                    Ok(WaitStatus::Continued(_)) => self.state = JobState::Running,
+                    Ok(WaitStatus::StillAlive) => break,
+                    Ok(_) | Err(Errno::EINTR) => continue,
+                    Err(_) => self.procs[i].done = true,
+                }
+            }
+        }
+
+        if self.procs.iter().all(|p| p.done) {
+            self.state = JobState::Done;
+        }
+    }
+
+    // Send a signal to every process in the job
+    pub fn signal(&self, sig: Signal) -> Result<(), String> {
+        match self.pgid {
+            Some(pgid) => kill(Pid::from_raw(-pgid.as_raw()), sig),
+            None => self
+                .procs
+                .iter()
+                .filter(|p| !p.done)
+                .try_for_each(|p| kill(p.pid, sig)),
+        }
+        .map_err(|e| format!("kill: {e}"))
+    }
+
+    pub fn describe(&self, current: bool) -> String {
+        let state = match self.state {
+            JobState::Running => "Running",
+            JobState::Stopped => "Stopped",
+            JobState::Done => "Done",
+        };
+        let mark = if current { "+" } else { " " };
+
+        format!("[{}]{mark} {state}\t{}", self.id, self.name)
+    }
+}
+
+impl Interpreter {
+    // Put the shell in its own process group in control of the terminal, so
+    // that jobs can be moved between the foreground and background
+    pub fn enable_job_control(&mut self) {
+        if !isatty(0).unwrap_or(false) {
+            return;
+        }
+
+        // Wait until we have been put in the foreground before taking the
+        // terminal, otherwise we would steal it from whoever started us
+        while let Ok(fg) = tcgetpgrp(0) {
+            if fg == getpgrp() {
+                break;
+            }
+            let _ = kill(Pid::from_raw(-getpgrp().as_raw()), Signal::SIGTTIN);
+        }
+
+        // The shell itself must never be stopped by the terminal
+        for sig in [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU] {
+            unsafe { signal(sig, SigHandler::SigIgn) }.unwrap();
+        }
+
+        let _ = setpgid(getpid(), getpid());
+        self.shell_pgid = getpgrp();
+        let _ = tcsetpgrp(0, self.shell_pgid);
+
+        self.job_control = true;
+    }
+
+    // Hand the terminal to a process group
+    pub fn give_terminal(&self, pgid: Option<Pid>) {
+        if let (true, Some(pgid)) = (self.job_control, pgid) {
+            let _ = tcsetpgrp(0, pgid);
+        }
+    }
+
+    // Wait for a job in the foreground until it exits or is stopped, storing
+    // its status in $?. Stopped jobs are moved into the job table.
+    pub fn wait_foreground(&mut self, mut job: Job) {
+        let pgid = job.pgid.map(|p| p.as_raw()).unwrap_or(0);
+        FOREGROUND.store(pgid, Ordering::SeqCst);
+
+        self.give_terminal(job.pgid);
+        job.update(true);
+        self.give_terminal(Some(self.shell_pgid));
+
+        FOREGROUND.store(0, Ordering::SeqCst);
+
+        // A job killed by ctrl-c interrupts whatever is being evaluated too,
+        // even though the shell didn't see the signal itself
+        if job.status.1 == Some(Signal::SIGINT) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        }
+
+        if job.state == JobState::Stopped {
+            self.set_status((128 + Signal::SIGTSTP as i32, Some(Signal::SIGTSTP)));
+
+            let id = self.add_job(job);
+            eprintln!("\n{}", self.jobs[id].describe(true));
+        } else {
+            self.set_status(job.status);
+        }
+    }
+
+    // Store the status of a command that has just finished in $?
+    pub fn set_status(&self, status: (i32, Option<Signal>)) {
+        let mut st = self.status.borrow_mut();
+        let cmd = st.start();
+        st.set(cmd, status);
+    }
+
+    // The exit code and signal for $?, from the last command to finish. A
+    // command whose output is still being read hasn't finished yet.
+    pub fn last_status(&self) -> (i32, Option<Signal>) {
+        let st = self.status.borrow();
+        (st.code, st.signal)
+    }
+
+    pub fn last_ret_code(&self) -> i32 {
+        self.last_status().0
+    }
+
+    // Add a job to the table, returning its index
+    pub fn add_job(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        self.jobs.push(job);
+        self.jobs.len() - 1
+    }
+
+    // Find the index of a job from a spec like %2 or 2, defaulting to the most
+    // recent job
+    pub fn find_job(&self, spec: Option<String>) -> Result<usize, String> {
+        match spec {
+            None => self
+                .jobs
+                .len()
+                .checked_sub(1)
+                .ok_or("no current job".to_string()),
+            Some(spec) => {
+                let id = spec
+                    .trim_start_matches('%')
+                    .parse::<usize>()
+                    .map_err(|_| format!("{spec}: not a job"))?;
+
+                self.jobs
+                    .iter()
+                    .position(|j| j.id == id)
+                    .ok_or(format!("{spec}: no such job"))
+            }
+        }
+    }
+
+    // Check on background jobs, reporting and forgetting the ones that finished
+    pub fn notify_jobs(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.update(false);
+        }
+
+        let current = self.jobs.len().saturating_sub(1);
+        for (i, job) in self.jobs.iter().enumerate() {
+            if job.state == JobState::Done {
+                eprintln!("{}", job.describe(i == current));
+            }
+        }
+
+        self.jobs.retain(|j| j.state != JobState::Done);
+    }
+}
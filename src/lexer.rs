@@ -1,12 +1,18 @@
 use std::collections::HashMap;
-use std::collections::LinkedList as List;
+use std::ops::Range;
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     LParen,
     RParen,
     Quote,
-    Ident(List<char>),
+    Backquote,
+    Unquote,
+    UnquoteSplice,
+    Ident(String),
+    Str(String),
+    // a here-doc whose terminator never came, holding its tag
+    Unterminated(String),
     EOF,
 }
 
@@ -15,114 +21,371 @@ fn hex_to_c(a: char, b: char) -> char {
     let a = a.to_digit(16).unwrap();
     let b = b.to_digit(16).unwrap();
     let c = (a << 4) | b;
-    let c = char::from_u32(c).unwrap();
-    c
+    char::from_u32(c).unwrap()
 }
 
+// Read the lines of a here-doc up to the line holding just its tag, which may
+// be indented. That indentation is removed from every line of the text.
+fn heredoc(
+    s: &mut impl Iterator<Item = (usize, char)>,
+    tag: &str,
+    end: &mut usize,
+) -> Option<String> {
+    let mut lines: Vec<String> = vec![];
+
+    loop {
+        let mut line = String::new();
+        let mut ended = false;
+        for (i, c) in s.by_ref() {
+            *end = i + 1;
+            if c == '\n' {
+                ended = true;
+                break;
+            }
+            line.push(c);
+        }
+
+        if line.trim() == tag {
+            let indent = |l: &str| l.chars().take_while(|c| c.is_whitespace()).count();
+            let strip = indent(&line);
+
+            let mut text = String::new();
+            for l in &lines {
+                text.extend(l.chars().skip(indent(l).min(strip)));
+                text.push('\n');
+            }
+            return Some(text);
+        }
+        if !ended {
+            return None;
+        }
+        lines.push(line);
+    }
+}
+
+// Split source text into tokens, each with the range of characters it came
+// from
 pub fn lex(
-    mut s: impl Iterator<Item = char>,
-    aliases: &HashMap<List<char>, List<List<char>>>,
-) -> Vec<Token> {
+    s: impl Iterator<Item = char>,
+    aliases: &HashMap<String, Vec<String>>,
+) -> Vec<(Token, Range<usize>)> {
     let mut tokens = Vec::new();
-    let mut stack = List::new();
+    let mut stack = String::new();
+    let mut s = s.enumerate().peekable();
 
     let mut in_comment = false;
     let mut in_quote = false;
     let mut last_is_paren = false;
 
-    let push = |s: &mut List<char>,
-                toks: &mut Vec<Token>,
+    // where the word or string being built up started
+    let mut start = 0;
+
+    // here-docs on the current line, as the index of their token and their
+    // tag, whose text starts on the next line
+    let mut heredocs: Vec<(usize, String)> = vec![];
+
+    // A #! line at the start of a script is skipped, so that scripts can be
+    // made executable
+    if s.next_if(|(_, c)| *c == '#').is_some() {
+        if s.next_if(|(_, c)| *c == '!').is_some() {
+            in_comment = true;
+        } else {
+            stack.push('#');
+        }
+    }
+
+    let push = |s: &mut String,
+                toks: &mut Vec<(Token, Range<usize>)>,
+                span: Range<usize>,
                 in_quote: bool,
                 last_is_paren: bool,
-                aliases: &HashMap<List<char>, List<List<char>>>| {
+                aliases: &HashMap<String, Vec<String>>| {
         if s.is_empty() && !in_quote {
             return;
         }
 
-        if last_is_paren {
-            if let Some(ps) = aliases.get(&s) {
+        // Quoted text is always a string, and never an alias
+        if in_quote {
+            toks.push((Token::Str(s.clone()), span));
+        } else if last_is_paren {
+            if let Some(ps) = aliases.get(s) {
                 for s in ps {
-                    toks.push(Token::Ident(s.clone()))
+                    toks.push((Token::Ident(s.clone()), span.clone()))
                 }
             } else {
-                toks.push(Token::Ident(s.clone()))
+                toks.push((Token::Ident(s.clone()), span))
             }
         } else {
-            toks.push(Token::Ident(s.clone()))
+            toks.push((Token::Ident(s.clone()), span))
         }
 
         s.clear();
     };
 
-    while let Some(c) = s.next() {
-        if c != '(' {}
+    let mut end = 0;
+    while let Some((i, c)) = s.next() {
+        end = i + 1;
 
         match c {
             '\n' if in_comment => {
                 in_comment = false;
+                start = i + 1;
             }
             _ if in_comment => {}
             '\"' => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+                // the quotes are part of the string's span
+                let span = if in_quote { start..i + 1 } else { start..i };
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    span,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
                 last_is_paren = false;
                 in_quote = !in_quote;
+                start = if in_quote { i } else { i + 1 };
             }
             '\\' => {
-                let next = s.next().unwrap();
+                let Some((_, next)) = s.next() else { break };
                 match next {
                     'n' => {
-                        stack.push_back('\n');
+                        stack.push('\n');
                     }
                     'x' => {
-                        let a = s.next().unwrap();
-                        let b = s.next().unwrap();
-                        stack.push_back(hex_to_c(a, b))
+                        let (Some((_, a)), Some((_, b))) = (s.next(), s.next()) else {
+                            break;
+                        };
+                        stack.push(hex_to_c(a, b))
                     }
-                    _ => stack.push_back(next),
+                    _ => stack.push(next),
                 }
             }
-            c if in_quote => stack.push_back(c),
+            c if in_quote => stack.push(c),
             c if c.is_whitespace() => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
                 last_is_paren = false;
+                start = i + 1;
             }
             ';' => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
                 last_is_paren = false;
                 in_comment = true;
             }
             '\'' => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
                 last_is_paren = false;
-                tokens.push(Token::Quote);
+                tokens.push((Token::Quote, i..i + 1));
+                start = i + 1;
+            }
+            '`' => {
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
+                last_is_paren = false;
+                tokens.push((Token::Backquote, i..i + 1));
+                start = i + 1;
+            }
+            // A comma only unquotes at the start of a word, so that words
+            // like a,b still reach commands whole
+            ',' if stack.is_empty() => {
+                last_is_paren = false;
+                if s.next_if(|(_, c)| *c == '@').is_some() {
+                    tokens.push((Token::UnquoteSplice, i..i + 2));
+                    start = i + 2;
+                    end = i + 2;
+                } else {
+                    tokens.push((Token::Unquote, i..i + 1));
+                    start = i + 1;
+                }
             }
             '(' => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
-                tokens.push(Token::LParen);
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
+                tokens.push((Token::LParen, i..i + 1));
                 last_is_paren = true;
+                start = i + 1;
             }
             ')' => {
-                push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+                push(
+                    &mut stack,
+                    &mut tokens,
+                    start..i,
+                    in_quote,
+                    last_is_paren,
+                    aliases,
+                );
                 last_is_paren = false;
-                tokens.push(Token::RParen);
+                tokens.push((Token::RParen, i..i + 1));
+                start = i + 1;
             }
-            _ => stack.push_back(c),
+            // <<TAG starts a here-doc, while other words starting with <<,
+            // like <<<, are left alone
+            '<' if stack.is_empty() && s.next_if(|(_, c)| *c == '<').is_some() => {
+                let mut tag = String::new();
+                if s.peek()
+                    .is_some_and(|(_, c)| c.is_alphabetic() || *c == '_')
+                {
+                    while let Some((j, c)) =
+                        s.next_if(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '-')
+                    {
+                        tag.push(c);
+                        end = j + 1;
+                    }
+                }
+
+                if tag.is_empty() {
+                    stack.push_str("<<");
+                } else {
+                    last_is_paren = false;
+                    tokens.push((Token::Str(String::new()), i..end));
+                    heredocs.push((tokens.len() - 1, tag));
+                    start = end;
+                }
+            }
+            _ => stack.push(c),
+        }
+
+        if c == '\n' && !in_quote && !heredocs.is_empty() {
+            for (tok, tag) in heredocs.drain(..) {
+                tokens[tok].0 = match heredoc(&mut s, &tag, &mut end) {
+                    Some(text) => Token::Str(text),
+                    None => Token::Unterminated(tag),
+                };
+            }
+            start = end;
         }
     }
-    push(&mut stack, &mut tokens, in_quote, last_is_paren, aliases);
+
+    // The text of a here-doc starts on the line after it, so one on the last
+    // line never gets any
+    for (tok, tag) in heredocs {
+        tokens[tok].0 = Token::Unterminated(tag);
+    }
+
+    push(
+        &mut stack,
+        &mut tokens,
+        start..end,
+        in_quote,
+        last_is_paren,
+        aliases,
+    );
 
     // In order to preserve somewhat normal behavior of the shell,
     // We automatically surround the input in a list if it is not alread a list
 
-    if tokens.len() != 0
-        && (tokens[0] != Token::LParen || tokens[tokens.len() - 1] != Token::RParen)
+    if !tokens.is_empty()
+        && (tokens[0].0 != Token::LParen || tokens[tokens.len() - 1].0 != Token::RParen)
     {
-        tokens.insert(0, Token::LParen);
-        tokens.push(Token::RParen);
+        let first = tokens[0].1.start;
+        let last = tokens[tokens.len() - 1].1.end;
+
+        tokens.insert(0, (Token::LParen, first..first));
+        tokens.push((Token::RParen, last..last));
     }
 
     // push EOF token
-    tokens.push(Token::EOF);
+    tokens.push((Token::EOF, end..end));
 
     tokens
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{lex, Token};
+
+    fn tokens(s: &str) -> Vec<Token> {
+        lex(s.chars(), &HashMap::new())
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect()
+    }
+
+    fn strs(s: &str) -> Vec<String> {
+        tokens(s)
+            .into_iter()
+            .filter_map(|t| match t {
+                Token::Str(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn heredoc_text_starts_on_the_next_line() {
+        assert_eq!(
+            tokens("(<<< <<EOF (cat))\nhello\n  world\nEOF\n"),
+            [
+                Token::LParen,
+                Token::Ident("<<<".into()),
+                Token::Str("hello\n  world\n".into()),
+                Token::LParen,
+                Token::Ident("cat".into()),
+                Token::RParen,
+                Token::RParen,
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn heredoc_indentation_follows_the_closing_tag() {
+        let s = "(do\n  (<<< <<END (cat))\n    a\n      b\n    END\n  (ls))";
+        assert_eq!(strs(s), ["a\n  b\n"]);
+        assert!(tokens(s).contains(&Token::Ident("ls".into())));
+    }
+
+    #[test]
+    fn heredocs_on_one_line_are_read_in_order() {
+        let s = "(list <<A <<B)\none\nA\ntwo\nB\n";
+        assert_eq!(strs(s), ["one\n", "two\n"]);
+    }
+
+    #[test]
+    fn unterminated_heredocs_are_reported() {
+        assert!(tokens("(<<< <<EOF (cat))\nhello\n").contains(&Token::Unterminated("EOF".into())));
+        assert!(tokens("(<<< <<EOF (cat))").contains(&Token::Unterminated("EOF".into())));
+    }
+
+    #[test]
+    fn other_words_starting_with_angle_brackets_are_left_alone() {
+        assert!(tokens("(<<< hi (cat))").contains(&Token::Ident("<<<".into())));
+        assert!(tokens("(<< 1 2)").contains(&Token::Ident("<<".into())));
+    }
+}
@@ -1,124 +1,184 @@
 pub mod builtins;
 pub mod complete;
+pub mod env;
+pub mod error;
+pub mod expand;
 pub mod input;
 pub mod interpreter;
+pub mod jobs;
 pub mod lexer;
+pub mod number;
 pub mod parser;
+pub mod process;
+pub mod stream;
+pub mod symbol;
+pub mod vector;
 
 pub use input::History;
 pub use input::Input;
 pub use interpreter::Interpreter;
 
-pub use std::collections::LinkedList as List;
+pub use std::collections::VecDeque as List;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use std::rc::Rc;
+
+use env::Lambda;
+pub use error::{Error, ErrorKind, Span};
+pub use stream::Stream;
+pub use symbol::Symbol;
+pub use vector::Vector;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SExpression {
-    Call(List<SExpression>),
-    List(List<SExpression>),
-    Atom(List<char>),
+    Call(List<SExpression>, Span),
+    List(Vector),
+    // output of a command, read into a list of lines as it is used
+    Stream(Stream),
+    Int(i64),
+    // integers too big for an i64
+    BigInt(Rc<num_bigint::BigInt>),
+    Ratio(Rc<num_rational::BigRational>),
+    Float(f64),
+    // a number written differently from how it prints, such as 0755 or 1.50,
+    // which keeps its text for when it is passed to a command
+    Numeral(Rc<str>, Rc<SExpression>),
+    Bool(bool),
+    Str(Rc<str>),
+    // raw output of a command, which isn't necessarily text
+    Bytes(Rc<[u8]>),
+    Symbol(Symbol),
+    Nil,
+    Function(Rc<Lambda>),
 }
 
 // some basic utility methods
 impl SExpression {
     pub fn len(&self) -> usize {
         match self {
-            Self::Atom(s) => s.len(),
-            Self::List(es) => es.iter().map(|e| e.len()).fold(0, |a, b| a + b),
-            Self::Call(_) => panic!("Called len on call expression"),
+            Self::Str(s) => s.chars().count(),
+            Self::Symbol(s) => s.name().chars().count(),
+            Self::List(es) => es.iter().map(|e| e.len()).sum(),
+            Self::Stream(s) => s.iter().map(|e| e.len()).sum(),
+            Self::Bytes(b) => b.len(),
+            Self::Nil => 0,
+            // code inside quasiquoted data counts as the list it looks like
+            Self::Call(es, _) => es.iter().map(|e| e.len()).sum(),
+            e => e.to_string().len(),
         }
     }
 
-    pub fn ident(self) -> List<char> {
+    pub fn is_empty(&self) -> bool {
         match self {
-            SExpression::Atom(chars) => chars,
-            SExpression::List(l) => {
-                let mut i = l.into_iter().map(|e| e.ident());
-
-                if let Some(first) = i.next() {
-                    i.fold(first, |mut a, mut e| {
-                        a.append(&mut e);
-                        a
-                    })
-                } else {
-                    List::new()
-                }
-            }
-            _ => panic!("Called ident on call expression"),
+            Self::Stream(s) => s.is_empty(),
+            e => e.len() == 0,
         }
     }
 
-    pub fn replace(self, from: &List<char>, to: SExpression) -> SExpression {
+    // Name of the type of a value, as used in error messages and by type-of
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Self::Atom(s) => {
-                if s == *from {
-                    to
-                } else {
-                    Self::Atom(s)
-                }
-            }
-            Self::Call(es) => Self::Call(
-                es.into_iter()
-                    .map(|e| e.replace(from, to.clone()))
-                    .collect(),
-            ),
-            a => a,
+            Self::Call(..) => "call",
+            Self::List(_) | Self::Stream(_) => "list",
+            Self::Int(_) | Self::BigInt(_) => "int",
+            Self::Ratio(_) => "ratio",
+            Self::Float(_) => "float",
+            Self::Numeral(_, n) => n.type_name(),
+            Self::Bool(_) => "bool",
+            Self::Str(_) => "string",
+            Self::Bytes(_) => "bytes",
+            Self::Symbol(_) => "symbol",
+            Self::Nil => "nil",
+            Self::Function(_) => "function",
         }
     }
 
-    pub fn list(self) -> List<List<char>> {
+    // The number a numeral stands for, or any other value as it is
+    pub fn plain(self) -> Self {
         match self {
-            Self::Call(es) | Self::List(es) => {
-                let mut i = es.into_iter().map(|e| e.list());
-
-                if let Some(first) = i.next() {
-                    i.fold(first, |mut a, mut e| {
-                        a.append(&mut e);
-                        a
-                    })
-                } else {
-                    List::new()
-                }
-            }
-            Self::Atom(s) => {
-                let mut l = List::new();
-                l.push_front(s);
-                l
-            }
+            Self::Numeral(_, n) => (*n).clone(),
+            e => e,
         }
     }
-}
 
-// Printing the parsed expression
-impl std::fmt::Display for SExpression {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // The text of a value, which is what external commands see when it is
+    // passed to them as an argument
+    pub fn ident(self) -> String {
         match self {
-            Self::List(es) => {
-                let l = self.len();
+            Self::Str(s) => s.to_string(),
+            Self::Symbol(s) => s.name().to_string(),
+            Self::Bytes(b) => String::from_utf8_lossy(&b).to_string(),
+            Self::Nil => String::new(),
+            Self::List(l) => l.into_iter().map(|e| e.ident()).collect(),
+            Self::Stream(s) => s.iter().map(|e| e.ident()).collect(),
+            Self::Call(es, _) => es.into_iter().map(|e| e.ident()).collect(),
+            e => e.to_string(),
+        }
+    }
 
-                f.write_str("(")?;
-                if l > 100 {
-                    f.write_str("\n\t")?;
-                }
+    // The raw bytes of a value, or its text for anything but bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Bytes(b) => b.to_vec(),
+            e => e.ident().into_bytes(),
+        }
+    }
 
-                for (i, e) in es.iter().enumerate() {
-                    if i != 0 && i != es.len() {
-                        if l > 100 {
-                            f.write_str("\n\t")?;
-                        } else {
-                            f.write_str(" ")?;
-                        }
-                    }
+    // The names in a possibly nested list, such as a parameter list
+    pub fn list(self) -> Vec<String> {
+        match self {
+            Self::Call(es, _) => es.into_iter().flat_map(|e| e.list()).collect(),
+            Self::List(es) => es.into_iter().flat_map(|e| e.list()).collect(),
+            e => vec![e.ident()],
+        }
+    }
+}
 
-                    e.fmt(f)?;
-                }
+pub fn type_error(expected: &str, e: &SExpression) -> Error {
+    Error::new(
+        ErrorKind::Type,
+        format!("expected {expected}, got {} {e}", e.type_name()),
+    )
+}
 
-                if l > 100 {
-                    f.write_str("\n")?;
-                }
+// Print the items of a list, one per line if together they are long. Only as
+// many items as it takes to tell are looked at before printing starts, so a
+// stream is printed as it is read.
+fn fmt_items<I>(f: &mut std::fmt::Formatter<'_>, items: I) -> std::fmt::Result
+where
+    I: Iterator<Item = SExpression> + Clone,
+{
+    let mut total = 0;
+    let long = items.clone().any(|e| {
+        total += e.len();
+        total > 100
+    });
+    let sep = if long { "\n\t" } else { " " };
+
+    f.write_str("(")?;
+    if long {
+        f.write_str("\n\t")?;
+    }
 
-                f.write_str(")")?;
-            }
-            Self::Call(es) => {
+    for (i, e) in items.enumerate() {
+        if i != 0 {
+            f.write_str(sep)?;
+        }
+        write!(f, "{e}")?;
+    }
+
+    if long {
+        f.write_str("\n")?;
+    }
+    f.write_str(")")
+}
+
+// Printing the parsed expression
+impl std::fmt::Display for SExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::List(es) => fmt_items(f, es.iter().cloned())?,
+            Self::Stream(s) => fmt_items(f, s.iter())?,
+            Self::Call(es, _) => {
                 f.write_str("(")?;
 
                 for (i, e) in es.iter().enumerate() {
@@ -131,19 +191,29 @@ impl std::fmt::Display for SExpression {
 
                 f.write_str(")")?;
             }
-            Self::Atom(s) => {
-                let s: String = s.iter().collect();
-
-                if s.contains(" ") {
-                    f.write_str("\"")?;
-                    f.write_str(&s)?;
-                    f.write_str("\"")?;
-                } else if s.is_empty() {
-                    f.write_str("\"\"")?;
-                } else {
-                    f.write_str(&s)?
+            Self::Int(n) => write!(f, "{n}")?,
+            Self::BigInt(n) => write!(f, "{n}")?,
+            Self::Ratio(n) => write!(f, "{n}")?,
+            // Debug keeps the decimal point on whole numbers
+            Self::Float(n) => write!(f, "{n:?}")?,
+            Self::Numeral(s, _) => f.write_str(s)?,
+            Self::Bool(b) => write!(f, "{b}")?,
+            Self::Str(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        c => write!(f, "{c}")?,
+                    }
                 }
+                f.write_str("\"")?;
             }
+            Self::Bytes(b) => write!(f, "<{} bytes>", b.len())?,
+            Self::Symbol(s) => s.fmt(f)?,
+            Self::Nil => f.write_str("nil")?,
+            Self::Function(func) => func.fmt(f)?,
         }
         Ok(())
     }
@@ -3,64 +3,189 @@ use lishp::Input;
 use lishp::Interpreter;
 use lishp::SExpression;
 
+use lishp::env::Scope;
+use lishp::interpreter::STACK_SIZE;
+use lishp::parser::is_complete;
+use nix::unistd::isatty;
+use std::collections::VecDeque as List;
+use std::path::PathBuf;
+
 fn main() {
+    std::thread::Builder::new()
+        .stack_size(*STACK_SIZE)
+        .spawn(run)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn run() {
     let mut it = Interpreter::load();
+    let mut args = std::env::args().skip(1).peekable();
+
+    // --norc skips the rc file and --rc FILE reads another one instead
+    let mut rc = None;
+    let mut norc = false;
+    loop {
+        match args.peek().map(String::as_str) {
+            Some("--norc") => norc = true,
+            Some("--rc") => {
+                args.next();
+                match args.peek() {
+                    Some(file) => rc = Some(PathBuf::from(file)),
+                    None => {
+                        eprintln!("--rc requires a file");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            _ => break,
+        }
+        args.next();
+    }
 
-    // If -c flag is used run the command from args and then exit, else start interpreter
-    let mut args = std::env::args();
-    match args.nth(1).as_ref().map(|s| s.as_str()) {
-        Some("-c") => {
+    // The user's own rc file is only read by interactive shells and -c
+    // commands, so that scripts and commands piped in behave the same for
+    // everyone. One given with --rc is always read.
+    let interactive = matches!(args.peek().map(String::as_str), Some("-c"))
+        || (args.peek().is_none() && isatty(0).unwrap_or(false));
+    if interactive && rc.is_none() {
+        rc = Interpreter::rc_path();
+    }
+    if let Some(rc) = rc.filter(|_| !norc) {
+        it.load_rc(&rc);
+    }
+
+    // If -c flag is used run the command from args and then exit, with a file
+    // run it as a script, else start interpreter
+    match args.next() {
+        Some(flag) if flag == "-c" => {
             let cmd = args.collect::<Vec<_>>();
             let cmd = cmd.join(" ");
             run_command(&mut it, &cmd);
+            std::process::exit(it.last_ret_code());
         }
-        _ => run_interactive(it),
+        Some(script) => run_script(it, &script, args.collect()),
+        None if isatty(0).unwrap_or(false) => run_interactive(it),
+        None => run_stdin(it),
     }
 }
 
+// Read commands from stdin when it isn't a terminal, such as when lishp is
+// piped into or run by another program. Lines are collected until every
+// parenthesis is closed, then evaluated without a prompt. Exits with the last
+// status.
+fn run_stdin(mut it: Interpreter) {
+    let mut buf = String::new();
+
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else { break };
+        buf.push_str(&line);
+        buf.push('\n');
+
+        if is_complete(&buf) {
+            run_source(&mut it, &buf);
+            buf.clear();
+        }
+    }
+
+    // Anything left is unfinished, which is reported as an error
+    if !buf.trim().is_empty() {
+        run_source(&mut it, &buf);
+    }
+
+    std::process::exit(it.last_ret_code());
+}
+
+// Evaluate some text from stdin, printing any value it returns
+fn run_source(it: &mut Interpreter, text: &str) {
+    match it.eval_all("<stdin>", text) {
+        Ok(SExpression::Nil) => {}
+        Ok(e) => {
+            it.finish(&e);
+            println!("{e}");
+        }
+        Err(e) => {
+            eprintln!("{}", e.render());
+            if it.last_ret_code() == 0 {
+                it.set_status((1, None));
+            }
+        }
+    }
+}
+
+// Run a script and exit with the status of the last command it ran, or 1 if
+// it failed with an error
+fn run_script(mut it: Interpreter, script: &str, args: Vec<String>) {
+    it.set_args(script, args);
+
+    if let Err(e) = it.run_file(script) {
+        eprintln!("{}", e.render());
+        if it.last_ret_code() == 0 {
+            it.set_status((1, None));
+        }
+    }
+
+    std::process::exit(it.last_ret_code());
+}
+
+// Evaluate a command, printing its value. An error leaves a failing status in
+// $? if the command didn't already set one.
 fn run_command(it: &mut Interpreter, cmd: &str) {
     match it.eval(cmd) {
-        Ok(e) => match e {
-            SExpression::Atom(s) if s.is_empty() => println!(""),
-            _ => println!("{e}"),
-        },
-        Err(e) => eprintln!("Error: {e}"),
+        Ok(e) => {
+            it.finish(&e);
+            match e {
+                SExpression::Nil => println!(),
+                _ => println!("{e}"),
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e.render());
+            if it.last_ret_code() == 0 {
+                it.set_status((1, None));
+            }
+        }
     }
 }
 
 fn run_interactive(mut it: Interpreter) {
-    // Ignore ctrl-c
-    ctrlc::set_handler(move || {}).unwrap();
+    // ctrl-c interrupts evaluation rather than killing the shell
+    lishp::jobs::handle_interrupts();
+    it.enable_job_control();
 
     let input = Input::new();
     let mut history = History::Nil;
 
     loop {
+        it.notify_jobs();
+
         let prompt = get_prompt(&mut it);
-        match input.readline(&prompt, history.clone()) {
-            Ok(s) => {
-                match history.clone() {
-                    History::Nil => {
-                        history = History::Cons(s.clone(), Box::new(history));
-                    }
-                    History::Cons(a, _) if a != s => {
-                        history = History::Cons(s.clone(), Box::new(history));
-                    }
-                    _ => {}
+        if let Ok(s) = input.readline(&prompt, history.clone()) {
+            match history.clone() {
+                History::Nil => {
+                    history = History::Cons(s.clone(), Box::new(history));
                 }
-
-                run_command(&mut it, &s);
+                History::Cons(a, _) if a != s => {
+                    history = History::Cons(s.clone(), Box::new(history));
+                }
+                _ => {}
             }
-            _ => {}
+
+            run_command(&mut it, &s);
         }
     }
 }
 
 fn get_prompt(it: &mut Interpreter) -> String {
-    if let Some(e) = it.defs.get(&"lishp_prompt".chars().collect()) {
-        if let Ok(s) = it.eval_expr(e.clone(), false) {
-            return s.ident().iter().collect();
-        }
+    // The prompt is either a fixed value or a function called each time
+    let prompt = match Scope::get(&it.globals, "lishp_prompt".into()) {
+        Some(SExpression::Function(f)) => it.apply(f, List::new()),
+        Some(e) => Ok(e),
+        None => return "> ".to_string(),
+    };
+    if let Ok(s) = prompt {
+        return s.ident();
     }
 
     "> ".to_string()
@@ -0,0 +1,302 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::{type_error, Error, SExpression};
+
+// A number in a form that arithmetic can be done on. Integers and ratios are
+// exact, and only turn into floats when combined with a float.
+#[derive(Debug, Clone)]
+pub enum Num {
+    Int(BigInt),
+    Ratio(BigRational),
+    Float(f64),
+}
+
+impl Num {
+    pub fn from_expr(e: &SExpression) -> Result<Self, Error> {
+        match e {
+            SExpression::Int(n) => Ok(Self::Int((*n).into())),
+            SExpression::BigInt(n) => Ok(Self::Int((**n).clone())),
+            SExpression::Ratio(n) => Ok(Self::Ratio((**n).clone())),
+            SExpression::Float(n) => Ok(Self::Float(*n)),
+            SExpression::Numeral(_, n) => Self::from_expr(n),
+            e => Err(type_error("number", e)),
+        }
+    }
+
+    // Integers that fit in an i64 are kept as one, and ratios of whole
+    // numbers become integers
+    pub fn into_expr(self) -> SExpression {
+        match self {
+            Self::Int(n) => match n.to_i64() {
+                Some(n) => SExpression::Int(n),
+                None => SExpression::BigInt(Rc::new(n)),
+            },
+            Self::Ratio(n) if n.is_integer() => Self::Int(n.to_integer()).into_expr(),
+            Self::Ratio(n) => SExpression::Ratio(Rc::new(n)),
+            Self::Float(n) => SExpression::Float(n),
+        }
+    }
+
+    // Read an integer, a ratio such as 7/2, or a float
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Ok(n) = s.parse() {
+            return Some(Self::Int(n));
+        }
+
+        if let Some((n, d)) = s.split_once('/') {
+            let (n, d): (BigInt, BigInt) = (n.parse().ok()?, d.parse().ok()?);
+            if d.is_zero() {
+                return None;
+            }
+            return Some(Self::Ratio(BigRational::new(n, d)));
+        }
+
+        match s.parse::<f64>() {
+            Ok(n) if n.is_finite() => Some(Self::Float(n)),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::Int(n) => n.to_f64().unwrap_or(f64::NAN),
+            Self::Ratio(n) => n.to_f64().unwrap_or(f64::NAN),
+            Self::Float(n) => *n,
+        }
+    }
+
+    fn to_ratio(&self) -> BigRational {
+        match self {
+            Self::Int(n) => BigRational::from_integer(n.clone()),
+            Self::Ratio(n) => n.clone(),
+            Self::Float(_) => unreachable!("floats are never made exact"),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Self::Int(n) => n.is_zero(),
+            Self::Ratio(n) => n.is_zero(),
+            Self::Float(n) => *n == 0.0,
+        }
+    }
+
+    // Bring two numbers to the same representation, the least exact of the two
+    fn coerce(self, other: Self) -> (Self, Self) {
+        match (&self, &other) {
+            (Self::Float(_), _) | (_, Self::Float(_)) => {
+                (Self::Float(self.to_f64()), Self::Float(other.to_f64()))
+            }
+            (Self::Ratio(_), _) | (_, Self::Ratio(_)) => {
+                (Self::Ratio(self.to_ratio()), Self::Ratio(other.to_ratio()))
+            }
+            _ => (self, other),
+        }
+    }
+
+    pub fn plus(self, other: Self) -> Result<Self, Error> {
+        Ok(match self.coerce(other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(a + b),
+            (Self::Ratio(a), Self::Ratio(b)) => Self::Ratio(a + b),
+            (a, b) => Self::Float(a.to_f64() + b.to_f64()),
+        })
+    }
+
+    pub fn minus(self, other: Self) -> Result<Self, Error> {
+        Ok(match self.coerce(other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(a - b),
+            (Self::Ratio(a), Self::Ratio(b)) => Self::Ratio(a - b),
+            (a, b) => Self::Float(a.to_f64() - b.to_f64()),
+        })
+    }
+
+    pub fn times(self, other: Self) -> Result<Self, Error> {
+        Ok(match self.coerce(other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(a * b),
+            (Self::Ratio(a), Self::Ratio(b)) => Self::Ratio(a * b),
+            (a, b) => Self::Float(a.to_f64() * b.to_f64()),
+        })
+    }
+
+    // Exact division gives a ratio when it isn't whole
+    pub fn divide(self, other: Self) -> Result<Self, Error> {
+        if !matches!(other, Self::Float(_)) && other.is_zero() {
+            return Err("division by zero".into());
+        }
+
+        Ok(match self.coerce(other) {
+            (Self::Float(a), Self::Float(b)) => Self::Float(a / b),
+            (a, b) => Self::Ratio(a.to_ratio() / b.to_ratio()),
+        })
+    }
+
+    // Remainder of truncating division, with the sign of the dividend
+    pub fn remainder(self, other: Self) -> Result<Self, Error> {
+        if !matches!(other, Self::Float(_)) && other.is_zero() {
+            return Err("division by zero".into());
+        }
+
+        Ok(match self.coerce(other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(a % b),
+            (Self::Ratio(a), Self::Ratio(b)) => Self::Ratio(a % b),
+            (a, b) => Self::Float(a.to_f64() % b.to_f64()),
+        })
+    }
+
+    // Quotient of division rounded towards zero, always an integer
+    pub fn quot(self, other: Self) -> Result<Self, Error> {
+        if other.is_zero() {
+            return Err("division by zero".into());
+        }
+
+        match self.coerce(other) {
+            (Self::Int(a), Self::Int(b)) => Ok(Self::Int(a / b)),
+            (Self::Float(a), Self::Float(b)) => float_to_int((a / b).trunc()),
+            (a, b) => Ok(Self::Int(
+                (a.to_ratio() / b.to_ratio()).trunc().to_integer(),
+            )),
+        }
+    }
+
+    pub fn pow(self, other: Self) -> Result<Self, Error> {
+        let exact = |base: BigRational, exp: &BigInt| -> Result<Self, Error> {
+            let e = exp
+                .abs()
+                .to_i32()
+                .ok_or_else(|| Error::from(format!("exponent {exp} is too large")))?;
+
+            if exp.is_negative() {
+                if base.is_zero() {
+                    return Err("division by zero".into());
+                }
+                Ok(Self::Ratio(base.pow(e).recip()))
+            } else {
+                Ok(Self::Ratio(base.pow(e)))
+            }
+        };
+
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) if !b.is_negative() => {
+                let e = b
+                    .to_u32()
+                    .ok_or_else(|| Error::from(format!("exponent {b} is too large")))?;
+                Ok(Self::Int(a.pow(e)))
+            }
+            (a @ (Self::Int(_) | Self::Ratio(_)), Self::Int(b)) => exact(a.to_ratio(), &b),
+            (a, b) => Ok(Self::Float(a.to_f64().powf(b.to_f64()))),
+        }
+    }
+
+    pub fn floor(self) -> Result<Self, Error> {
+        match self {
+            Self::Int(n) => Ok(Self::Int(n)),
+            Self::Ratio(n) => Ok(Self::Int(n.floor().to_integer())),
+            Self::Float(n) => float_to_int(n.floor()),
+        }
+    }
+
+    // Round to the nearest integer, with halves rounded away from zero
+    pub fn round(self) -> Result<Self, Error> {
+        match self {
+            Self::Int(n) => Ok(Self::Int(n)),
+            Self::Ratio(n) => Ok(Self::Int(n.round().to_integer())),
+            Self::Float(n) => float_to_int(n.round()),
+        }
+    }
+
+    pub fn float(self) -> Self {
+        Self::Float(self.to_f64())
+    }
+
+    pub fn compare(&self, other: &Self) -> Result<Ordering, Error> {
+        match self.clone().coerce(other.clone()) {
+            (Self::Int(a), Self::Int(b)) => Ok(a.cmp(&b)),
+            (Self::Ratio(a), Self::Ratio(b)) => Ok(a.cmp(&b)),
+            (a, b) => a
+                .to_f64()
+                .partial_cmp(&b.to_f64())
+                .ok_or("cannot compare NaN".into()),
+        }
+    }
+}
+
+// Convert a whole float to an integer
+fn float_to_int(n: f64) -> Result<Num, Error> {
+    BigRational::from_float(n)
+        .map(|n| Num::Int(n.to_integer()))
+        .ok_or_else(|| format!("cannot convert {n} to an integer").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Num;
+
+    fn num(s: &str) -> Num {
+        Num::parse(s).unwrap()
+    }
+
+    fn show<E: std::fmt::Debug>(n: Result<Num, E>) -> String {
+        n.unwrap().into_expr().to_string()
+    }
+
+    #[test]
+    fn parses_each_kind_of_number() {
+        assert!(matches!(num("42"), Num::Int(_)));
+        assert!(matches!(num("-7/2"), Num::Ratio(_)));
+        assert!(matches!(num("1.5"), Num::Float(_)));
+        assert!(matches!(num("123456789012345678901234567890"), Num::Int(_)));
+        assert!(Num::parse("1/0").is_none());
+        assert!(Num::parse("inf").is_none());
+        assert!(Num::parse("abc").is_none());
+    }
+
+    #[test]
+    fn exact_arithmetic_stays_exact() {
+        assert_eq!(show(num("1").divide(num("3"))), "1/3");
+        assert_eq!(show(num("1/3").plus(num("2/3"))), "1");
+        assert_eq!(show(num("6").divide(num("3"))), "2");
+        assert_eq!(show(num("2").pow(num("-2"))), "1/4");
+        assert_eq!(
+            show(num("9223372036854775807").plus(num("1"))),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            show(num("9223372036854775808").minus(num("1"))),
+            "9223372036854775807"
+        );
+    }
+
+    #[test]
+    fn floats_are_contagious() {
+        assert_eq!(show(num("1/2").plus(num("0.25"))), "0.75");
+        assert_eq!(show(num("3").times(num("0.5"))), "1.5");
+        assert_eq!(show(num("1").divide(num("0.0"))), "inf");
+    }
+
+    #[test]
+    fn integer_division_and_rounding() {
+        assert_eq!(show(num("-7").quot(num("2"))), "-3");
+        assert_eq!(show(num("-7").remainder(num("2"))), "-1");
+        assert_eq!(show(num("7/2").floor()), "3");
+        assert_eq!(show(num("-5/2").round()), "-3");
+        assert_eq!(show(num("2.5").round()), "3");
+        assert!(num("1").divide(num("0")).is_err());
+        assert!(num("1").quot(num("0.0")).is_err());
+        assert!(num("0").pow(num("-1")).is_err());
+    }
+
+    #[test]
+    fn compares_across_kinds() {
+        use std::cmp::Ordering;
+
+        assert_eq!(num("1/2").compare(&num("0.5")).unwrap(), Ordering::Equal);
+        assert_eq!(num("1/3").compare(&num("0.3")).unwrap(), Ordering::Greater);
+        assert_eq!(num("2").compare(&num("5/2")).unwrap(), Ordering::Less);
+        assert!(num("1").compare(&Num::Float(f64::NAN)).is_err());
+    }
+}
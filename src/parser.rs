@@ -1,89 +1,207 @@
-use std::fs::File;
-use std::io::Read;
-use std::collections::LinkedList as List;
 use std::collections::HashMap;
+use std::collections::VecDeque as List;
+use std::ops::Range;
+use std::rc::Rc;
 
-use crate::SExpression;
+use crate::error::{Error, Source, Span};
 use crate::lexer::{lex, Token};
+use crate::number::Num;
+use crate::{SExpression, Symbol};
 
 impl SExpression {
-    pub fn parse(s: &str, aliases: &HashMap<List<char>, List<List<char>>>) -> Result<Self, String> {
+    pub fn parse(s: &str, aliases: &HashMap<String, Vec<String>>) -> Result<Self, Error> {
+        let source = Rc::new(Source {
+            name: "<input>".to_string(),
+            text: s.to_string(),
+        });
         let toks = lex(s.chars(), aliases);
         let mut iter = toks.into_iter().peekable();
 
-        if let Some(Token::EOF) = iter.peek() {
-            Ok(Self::Atom(List::new()))
+        if let Some((Token::EOF, _)) = iter.peek() {
+            Ok(Self::Nil)
         } else {
-            Self::parse_toks(&mut iter)
+            Self::parse_toks(&mut iter, &source)
         }
     }
 
-    fn parse_toks<I>(t: &mut std::iter::Peekable<I>) -> Result<Self, String>
-    where I: Iterator<Item = Token> + std::fmt::Debug {
+    // Quoted code is data, so calls nested anywhere in it become lists
+    fn quoted(self) -> Self {
+        match self {
+            Self::Call(es, _) => Self::List(es.into_iter().map(Self::quoted).collect()),
+            e => e,
+        }
+    }
+
+    // Numbers that print differently from how they are written, such as 0755
+    // or 1.50, keep their text so that they reach commands unchanged
+    fn atom(s: String) -> Self {
+        match s.as_str() {
+            "true" => return Self::Bool(true),
+            "false" => return Self::Bool(false),
+            "nil" => return Self::Nil,
+            _ => {}
+        }
+
+        if let Some(n) = Num::parse(&s).map(Num::into_expr) {
+            return if n.to_string() == s {
+                n
+            } else {
+                Self::Numeral(s.into(), Rc::new(n))
+            };
+        }
+
+        Self::Symbol(Symbol::new(&s))
+    }
+
+    fn parse_toks<I>(t: &mut std::iter::Peekable<I>, source: &Rc<Source>) -> Result<Self, Error>
+    where
+        I: Iterator<Item = (Token, Range<usize>)>,
+    {
         match t.next() {
-            None | Some(Token::EOF) => Err("Unexpected EOF".into()),
-            Some(Token::Quote) => {
-                let next = Self::parse_toks(t)?;
-
-                Ok(if let Self::Call(es) = next {
-                    Self::List(es)
-                } else {
-                    next
-                })
+            None => Err(Error::parse("Unexpected EOF", Span::default())),
+            Some((Token::EOF, span)) => Err(Error::parse(
+                "Unexpected EOF",
+                Span::new(source, span.start, span.end),
+            )),
+            Some((Token::Quote, _)) => Ok(Self::parse_toks(t, source)?.quoted()),
+            // `x, ,x and ,@x are read as (quasiquote x), (unquote x) and
+            // (unquote-splicing x)
+            Some((tok @ (Token::Backquote | Token::Unquote | Token::UnquoteSplice), span)) => {
+                let name = match tok {
+                    Token::Backquote => "quasiquote",
+                    Token::Unquote => "unquote",
+                    _ => "unquote-splicing",
+                };
+                let next = Self::parse_toks(t, source)?;
+                let end = match &next {
+                    Self::Call(_, s) => s.end,
+                    _ => span.end,
+                };
+
+                Ok(Self::Call(
+                    List::from([Self::Symbol(Symbol::new(name)), next]),
+                    Span::new(source, span.start, end),
+                ))
             }
-            Some(Token::LParen) => {
+            Some((Token::LParen, open)) => {
                 let mut es = List::new();
 
                 loop {
                     match t.peek() {
-                        None => return Err("Unexpected EOF".into()),
-                        Some(tok) => {
-                            if *tok == Token::RParen { break; }
-                            es.push_back(Self::parse_toks(t)?);
+                        None | Some((Token::EOF, _)) => {
+                            return Err(Error::parse(
+                                "unclosed parenthesis",
+                                Span::new(source, open.start, open.end),
+                            ))
+                        }
+                        Some((tok, _)) => {
+                            if *tok == Token::RParen {
+                                break;
+                            }
+                            es.push_back(Self::parse_toks(t, source)?);
                         }
                     }
                 }
 
-                t.next();
+                let close = t.next().map_or(open.end, |(_, span)| span.end);
 
-                Ok(Self::Call(es))
+                Ok(Self::Call(es, Span::new(source, open.start, close)))
             }
-            Some(Token::Ident(i)) => {
-                Ok(Self::Atom(i))
-            },
-            a => Err(format!("Unexpected token: {:?}", a))
+            Some((Token::Ident(i), _)) => Ok(Self::atom(i)),
+            Some((Token::Str(s), _)) => Ok(Self::Str(s.into())),
+            Some((Token::Unterminated(tag), span)) => Err(Error::parse(
+                format!("here-doc is missing its closing {tag}"),
+                Span::new(source, span.start, span.end),
+            )),
+            Some((a, span)) => Err(Error::parse(
+                format!("Unexpected token: {:?}", a),
+                Span::new(source, span.start, span.end),
+            )),
         }
     }
 }
 
-pub fn parse_file(s: &str) -> Vec<SExpression> {
-    let meta = std::fs::metadata(s);
-    if meta.is_err() {
-        return vec![];
+pub fn parse_file(path: &str) -> Result<Vec<SExpression>, Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| Error::io(format!("{path}: {e}")))?;
+
+    parse_str(path, &text, &HashMap::new())
+}
+
+// Parse every expression in some source, where name is used to refer to it in
+// error messages
+pub fn parse_str(
+    name: &str,
+    s: &str,
+    aliases: &HashMap<String, Vec<String>>,
+) -> Result<Vec<SExpression>, Error> {
+    let source = Rc::new(Source {
+        name: name.to_string(),
+        text: s.to_string(),
+    });
+    let mut tokens = lex(s.chars(), aliases).into_iter().peekable();
+    let mut exprs = vec![];
+
+    while !matches!(tokens.peek(), Some((Token::EOF, _)) | None) {
+        exprs.push(SExpression::parse_toks(&mut tokens, &source)?);
     }
 
-    let meta = meta.unwrap();
-    let mut buf = String::with_capacity(meta.len() as usize);
+    Ok(exprs)
+}
 
-    let mut f = File::open(s).unwrap();
-    f.read_to_string(&mut buf).unwrap();
+// Whether every parenthesis opened in some text has been closed, and every
+// here-doc ended, so that a reader knows when it has a whole expression
+pub fn is_complete(s: &str) -> bool {
+    let mut depth = 0;
+    for (tok, _) in lex(s.chars(), &HashMap::new()) {
+        match tok {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Unterminated(_) => return false,
+            _ => {}
+        }
+    }
 
-    parse_str(&buf)
+    depth <= 0
 }
 
-pub fn parse_str(s: &str) -> Vec<SExpression> {
-    let mut tokens = lex(s.chars(), &HashMap::new()).into_iter().peekable();
-    let mut exprs = vec![];
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, SExpression};
 
-    while tokens.peek() != Some(&Token::EOF) {
-        match SExpression::parse_toks(&mut tokens) {
-            Ok(e) => exprs.push(e),
-            Err(e) => {
-                eprintln!("Error parsing: {e}");
-                break;
-            }
-        }
+    #[test]
+    fn quoted_lists_are_data_all_the_way_down() {
+        let mut it = Interpreter::load();
+
+        let e = it.eval("(do '(a (b c)))").unwrap();
+        let SExpression::List(es) = &e else {
+            panic!("expected a list, got {e}");
+        };
+        assert!(matches!(es.iter().nth(1), Some(SExpression::List(_))));
+        assert_eq!(
+            it.eval("(get 'b '((a 1) (b 2)))").unwrap(),
+            SExpression::Int(2)
+        );
     }
 
-    exprs
+    #[test]
+    fn quasiquoted_code_can_be_passed_to_commands() {
+        let mut it = Interpreter::load();
+
+        let e = it.eval("(let ((x 1)) `(a ,x (b ,x)))").unwrap();
+        assert_eq!(e.to_string(), "(a 1 (b 1))");
+        assert_eq!(e.ident(), "a1b1");
+    }
+
+    #[test]
+    fn numerals_are_numbers_that_keep_their_text() {
+        let mut it = Interpreter::load();
+
+        assert_eq!(it.eval("(+ 1 1.50)").unwrap(), SExpression::Float(2.5));
+        assert_eq!(it.eval("(* 2 1e3)").unwrap(), SExpression::Float(2000.0));
+        assert_eq!(it.eval("(= 0755 755)").unwrap(), SExpression::Bool(true));
+        assert_eq!(
+            it.eval("(first (echo 0755 1.50 2/4))").unwrap(),
+            SExpression::Str("0755 1.50 2/4".into())
+        );
+    }
 }
@@ -1,6 +1,6 @@
 ; Test if a collection is empty
 (defun empty? (a)
-  (or (= a '()) (= a "")))
+  (or (nil? a) (= a '()) (= a "")))
 
 
 ; Accessors for extra elements in a list
@@ -10,6 +10,14 @@
 (defun third (x)
   (first (first (rest x))))
 
+; Look up a key in an association list such as the one returned by run
+(defun get (k l)
+  (if (empty? l)
+    '()
+    (if (= (first (first l)) k)
+      (second (first l))
+      (get k (rest l)))))
+
 ; Get the nth item in a list l
 (defun nth (n l)
   (if (= n 0)
@@ -18,9 +26,12 @@
 
 ; Get the size of a list
 (defun count (l)
+  (count-h 0 l))
+
+(defun count-h (n l)
   (if (empty? l)
-    0
-    (+ 1 (count (rest l)))))
+    n
+    (count-h (+ n 1) (rest l))))
 
 ; Regular functional operators map, filter, reduces
 (defun map (f l)
@@ -35,10 +46,15 @@
       (cons (first l) (filter f (rest l)))
       (filter f (rest l)))))
 
-(defun reduce (f l)
-  (if (empty? (rest l))
-    (first l)
-    (f (first l) (reduce f (rest l)))))
+; reduce folds from the right, optionally starting from an initial value
+(defun reduce (f l ...init)
+  (if (empty? init)
+    (if (empty? (rest l))
+      (first l)
+      (f (first l) (reduce f (rest l))))
+    (if (empty? l)
+      (first init)
+      (f (first l) (reduce f (rest l) (first init))))))
 
 
 ; Test if a list contains an element s
@@ -117,4 +133,17 @@
       (sappend to (replace (drop (count from) s) from to))
       (cons (first s) (replace (rest s) from to)))))
 
-(alias ~ /home/devin)
+; The last item of a list l
+(defun last (l)
+  (if (empty? (rest l))
+    (first l)
+    (last (rest l))))
+
+; Evaluate body in the directory dir, changing back afterwards even if it
+; fails
+(defmacro with-dir (dir ...body)
+  `(with-dir-h (first (pwd)) ,dir (lambda () (do ,@body))))
+
+(defun with-dir-h (old dir f)
+  (try (cd dir) (f) (finally (cd old))))
+
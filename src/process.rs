@@ -0,0 +1,655 @@
+use std::collections::VecDeque as List;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::rc::Rc;
+
+use crate::builtins::{BUILTINS, SPECIAL_FORMS};
+use crate::env::Scope;
+use crate::expand::{expand_one, expand_value};
+use crate::jobs::Job;
+use crate::stream::Stream;
+use crate::Interpreter;
+use crate::SExpression;
+use crate::{Error, ErrorKind};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::{
+    close, dup2, execv, fork, getpgrp, pipe2, read, setpgid, tcsetpgrp, write, ForkResult, Pid,
+};
+
+// An external command that has been resolved on the path and had its
+// arguments evaluated, ready to be forked and exec'd
+pub struct Command {
+    pub bin: CString,
+    pub args: Vec<CString>,
+    pub redirects: Redirects,
+}
+
+// Files that a command's standard streams have been redirected to. These take
+// priority over any pipe the command is connected to, and are shared between
+// every stage of a pipeline that a redirection wraps.
+#[derive(Default, Clone)]
+pub struct Redirects {
+    pub stdin: Option<Rc<File>>,
+    pub stdout: Option<Rc<File>>,
+    pub stderr: Option<Rc<File>>,
+}
+
+// Open the file named by a redirection operator and attach it to the stages
+// of a job. Redirections closer to the command win, so only streams that
+// aren't already redirected are touched.
+pub fn redirect(op: &str, path: &str, stages: &mut [Command]) -> Result<(), Error> {
+    let mut opts = File::options();
+    match op {
+        "<" => opts.read(true),
+        ">>" => opts.append(true).create(true),
+        _ => opts.write(true).create(true).truncate(true),
+    };
+
+    let f = Rc::new(
+        opts.open(path)
+            .map_err(|e| Error::io(format!("{op}: {path}: {e}")))?,
+    );
+
+    if stages.is_empty() {
+        return Ok(());
+    }
+    let last = stages.len() - 1;
+
+    match op {
+        "<" => {
+            stages[0].redirects.stdin.get_or_insert(f);
+        }
+        ">" | ">>" => {
+            stages[last].redirects.stdout.get_or_insert(f);
+        }
+        "2>" => {
+            for stage in stages.iter_mut() {
+                stage.redirects.stderr.get_or_insert(f.clone());
+            }
+        }
+        "&>" => {
+            stages[last].redirects.stdout.get_or_insert(f.clone());
+            for stage in stages.iter_mut() {
+                stage.redirects.stderr.get_or_insert(f.clone());
+            }
+        }
+        _ => return Err(format!("unknown redirection {op}").into()),
+    }
+
+    Ok(())
+}
+
+// Send data to the stdin of the first stage of a job, unless it has been
+// redirected. It is written from a thread so that the shell doesn't block on
+// a command that reads slowly, and the thread gives up if the command exits
+// without reading all of it.
+pub fn feed(data: Vec<u8>, stages: &mut [Command]) -> Result<(), Error> {
+    let Some(first) = stages.first_mut().filter(|c| c.redirects.stdin.is_none()) else {
+        return Ok(());
+    };
+
+    let (r, w) = cloexec_pipe()?;
+    first.redirects.stdin = Some(Rc::new(unsafe { File::from_raw_fd(r) }));
+
+    std::thread::spawn(move || {
+        let mut w = unsafe { File::from_raw_fd(w) };
+        let _ = w.write_all(&data);
+    });
+
+    Ok(())
+}
+
+// The bytes to send to a command's stdin for a value. Text that isn't empty
+// ends with a newline, like a line typed at a terminal, and lists are written
+// one item per line.
+pub fn input(e: SExpression) -> Vec<u8> {
+    let line = |e: SExpression| {
+        let mut text = e.ident();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.into_bytes()
+    };
+
+    match e {
+        SExpression::Bytes(b) => b.to_vec(),
+        SExpression::Nil => vec![],
+        SExpression::List(es) => es.into_iter().flat_map(line).collect(),
+        SExpression::Stream(s) => s.iter().flat_map(line).collect(),
+        e => line(e),
+    }
+}
+
+// Create a pipe whose ends are closed automatically on exec, so that children
+// only ever inherit the ends that have been dup2'd onto their std fds
+pub fn cloexec_pipe() -> Result<(RawFd, RawFd), String> {
+    pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("failed to create pipe: {e}"))
+}
+
+// How the processes of a job are grouped when job control is enabled. A pgid
+// of 0 starts a new group led by the process being spawned.
+#[derive(Clone, Copy)]
+pub struct Group {
+    pub pgid: Pid,
+    pub foreground: bool,
+    pub stoppable: bool,
+}
+
+// Why a command couldn't be started, with the exit code to report for it
+pub struct SpawnError {
+    pub code: i32,
+    pub msg: String,
+    // a child that failed to exec, which exits with code and still has to
+    // be waited for
+    pub pid: Option<Pid>,
+}
+
+// Describe a failed exec the way other shells do. The binary was found on the
+// path, so a missing file means its #! interpreter doesn't exist.
+fn exec_error(name: &str, e: Errno, pid: Pid) -> SpawnError {
+    let (code, msg) = match e {
+        Errno::ENOENT => (127, format!("{name}: bad interpreter: {}", e.desc())),
+        Errno::ENOEXEC => (
+            126,
+            format!("{name}: cannot execute binary file: {}", e.desc()),
+        ),
+        e => (126, format!("{name}: {}", e.desc())),
+    };
+
+    SpawnError {
+        code,
+        msg,
+        pid: Some(pid),
+    }
+}
+
+// Fork and exec a command, connecting its standard streams to the given fds
+// unless the command redirects them to a file. A value of None leaves the fd
+// inherited from the shell.
+pub fn spawn(
+    cmd: &Command,
+    stdin: Option<RawFd>,
+    stdout: Option<RawFd>,
+    stderr: Option<RawFd>,
+    group: Option<Group>,
+) -> Result<Pid, SpawnError> {
+    let fd = |f: &Option<Rc<File>>| f.as_ref().map(|f| f.as_raw_fd());
+
+    let stdin = fd(&cmd.redirects.stdin).or(stdin);
+    let stdout = fd(&cmd.redirects.stdout).or(stdout);
+    let stderr = fd(&cmd.redirects.stderr).or(stderr);
+
+    // If exec fails the child sends back its errno on this pipe. Otherwise
+    // exec closes it and the parent reads nothing.
+    let (err_r, err_w) = cloexec_pipe().map_err(|msg| SpawnError {
+        code: 1,
+        msg,
+        pid: None,
+    })?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            // Also set the group from the parent so that it is in place no
+            // matter which process gets scheduled first
+            if let Some(g) = group {
+                let pgid = if g.pgid.as_raw() == 0 { child } else { g.pgid };
+                let _ = setpgid(child, pgid);
+            }
+
+            close(err_w).unwrap();
+            let mut buf = [0; 4];
+            let n = loop {
+                match read(err_r, &mut buf) {
+                    Err(Errno::EINTR) => continue,
+                    n => break n.unwrap_or(0),
+                }
+            };
+            close(err_r).unwrap();
+
+            if n == buf.len() {
+                let name = cmd.args[0].to_string_lossy();
+                let e = Errno::from_i32(i32::from_ne_bytes(buf));
+                return Err(exec_error(&name, e, child));
+            }
+
+            Ok(child)
+        }
+        Ok(ForkResult::Child) => {
+            if let Some(g) = group {
+                let _ = setpgid(Pid::from_raw(0), g.pgid);
+                if g.foreground {
+                    let _ = tcsetpgrp(0, getpgrp());
+                }
+
+                // Undo the signals the shell ignores. Commands whose output
+                // is being captured can't be suspended since the shell is
+                // blocked reading from them.
+                for sig in [Signal::SIGTTIN, Signal::SIGTTOU] {
+                    unsafe { signal(sig, SigHandler::SigDfl) }.unwrap();
+                }
+                if g.stoppable {
+                    unsafe { signal(Signal::SIGTSTP, SigHandler::SigDfl) }.unwrap();
+                }
+            }
+
+            // Rust ignores SIGPIPE, which commands would inherit. A command
+            // whose output stops being read should be killed by it instead.
+            unsafe { signal(Signal::SIGPIPE, SigHandler::SigDfl) }.unwrap();
+
+            if let Some(fd) = stdin {
+                dup2(fd, 0).unwrap();
+            }
+            if let Some(fd) = stdout {
+                dup2(fd, 1).unwrap();
+            }
+            if let Some(fd) = stderr {
+                dup2(fd, 2).unwrap();
+            }
+
+            let Err(e) = execv(&cmd.bin, &cmd.args);
+            let _ = write(err_w, &(e as i32).to_ne_bytes());
+            let code = if e == Errno::ENOENT { 127 } else { 126 };
+            unsafe { libc::_exit(code) }
+        }
+        Err(e) => {
+            close(err_r).unwrap();
+            close(err_w).unwrap();
+            Err(SpawnError {
+                code: 1,
+                msg: format!("failed to fork: {e}"),
+                pid: None,
+            })
+        }
+    }
+}
+
+fn spawn_error_kind(code: i32) -> ErrorKind {
+    match code {
+        127 => ErrorKind::CommandNotFound,
+        _ => ErrorKind::Io,
+    }
+}
+
+// The captured output of a pipeline
+pub struct Output {
+    pub stdout: Option<Vec<u8>>,
+    pub stderr: Option<Vec<u8>>,
+    pub status: i32,
+}
+
+// Split captured output into a list of lines
+pub fn lines(out: &[u8]) -> SExpression {
+    let out = String::from_utf8_lossy(out);
+    SExpression::List(out.lines().map(|s| SExpression::Str(s.into())).collect())
+}
+
+// Read everything from fd until EOF
+fn read_all(fd: RawFd) -> Vec<u8> {
+    let mut out = vec![];
+    let mut buf = [0; 1024];
+
+    while let Ok(n) = read(fd, &mut buf) {
+        if n == 0 {
+            break;
+        } // EOF
+        out.extend_from_slice(&buf[0..n]);
+    }
+
+    out
+}
+
+impl Interpreter {
+    // Resolve a command name on the path and evaluate its arguments into strings
+    pub fn command(&mut self, name: String, args: List<SExpression>) -> Result<Command, Error> {
+        let bin = match self.search_path(&name) {
+            Some(bin) => bin,
+            None => {
+                self.set_status((127, None));
+                return Err(Error::new(
+                    ErrorKind::CommandNotFound,
+                    format!("command not found: {}", name),
+                ));
+            }
+        };
+
+        let mut fargs = vec![];
+        fargs.push(CString::new(name).unwrap());
+
+        for arg in args {
+            for arg in expand_value(self.eval_expr(arg, false)?) {
+                fargs.push(
+                    CString::new(arg).map_err(|_| Error::from("argument contains a nul byte"))?,
+                );
+            }
+        }
+
+        Ok(Command {
+            bin,
+            args: fargs,
+            redirects: Redirects::default(),
+        })
+    }
+
+    // Turn a call expression such as (grep foo) into the commands it runs.
+    // Pipes and redirections may be nested inside each other, e.g.
+    // (> out.txt (| (< in.txt (sort)) (uniq)))
+    pub fn job(&mut self, e: SExpression) -> Result<Vec<Command>, Error> {
+        match e {
+            SExpression::Call(mut es, _) => {
+                let head = es
+                    .pop_front()
+                    .ok_or_else(|| Error::from("Empty Call Expression"))?;
+                let name = head.clone().ident();
+
+                match name.as_str() {
+                    "|" | "pipe" => {
+                        let mut stages = vec![];
+                        for e in es {
+                            stages.extend(self.job(e)?);
+                        }
+                        Ok(stages)
+                    }
+                    ">" | ">>" | "<" | "2>" | "&>" => {
+                        if let (Some(file), Some(inner), None) =
+                            (es.pop_front(), es.pop_front(), es.pop_front())
+                        {
+                            let file = expand_one(self.eval_expr(file, false)?)?;
+                            self.redirect_job(&name, file, inner)
+                        } else {
+                            Err(Error::arity(format!(
+                                "{name} requires a file and a command"
+                            )))
+                        }
+                    }
+                    "<<<" => {
+                        if let (Some(data), Some(inner), None) =
+                            (es.pop_front(), es.pop_front(), es.pop_front())
+                        {
+                            let data = self.eval_expr(data, false)?;
+                            self.feed_job(data, inner)
+                        } else {
+                            Err(Error::arity("<<< requires an input and a command"))
+                        }
+                    }
+                    _ => {
+                        self.check_command(&head)?;
+                        Ok(vec![self.command(name, es)?])
+                    }
+                }
+            }
+            e => Err(Error::new(ErrorKind::Type, format!("{e} is not a command"))),
+        }
+    }
+
+    // Only external commands can be the stages of a pipeline or redirection,
+    // since functions and builtins don't run in a process of their own
+    fn check_command(&self, head: &SExpression) -> Result<(), Error> {
+        let what = match head {
+            SExpression::Symbol(name) => match Scope::get(&self.env, *name) {
+                Some(SExpression::Function(_)) => "function",
+                _ if BUILTINS.contains_key(&*name.name())
+                    || SPECIAL_FORMS.contains_key(&*name.name()) =>
+                {
+                    "builtin"
+                }
+                _ => return Ok(()),
+            },
+            SExpression::Call(..) | SExpression::Function(_) => "function",
+            _ => return Ok(()),
+        };
+
+        Err(Error::new(
+            ErrorKind::Type,
+            format!("{head} is a {what}, not a command, so it can't be piped or redirected"),
+        ))
+    }
+
+    // Build the commands for a job with a redirection applied to them
+    pub fn redirect_job(
+        &mut self,
+        op: &str,
+        file: String,
+        inner: SExpression,
+    ) -> Result<Vec<Command>, Error> {
+        let mut stages = self.job(inner)?;
+        redirect(op, &file, &mut stages)?;
+        Ok(stages)
+    }
+
+    // Build the commands for a job that reads a value from its stdin
+    pub fn feed_job(
+        &mut self,
+        data: SExpression,
+        inner: SExpression,
+    ) -> Result<Vec<Command>, Error> {
+        let data = input(data);
+        let mut stages = self.job(inner)?;
+        feed(data, &mut stages)?;
+        Ok(stages)
+    }
+
+    // A new process group for a job, if job control is enabled
+    pub fn new_group(&self, foreground: bool, stoppable: bool) -> Option<Group> {
+        self.job_control.then_some(Group {
+            pgid: Pid::from_raw(0),
+            foreground,
+            stoppable,
+        })
+    }
+
+    // Fork every stage of a pipeline into a single job, with the stdout of
+    // each stage connected to the stdin of the next. The last stage writes to
+    // stdout and every stage to stderr, or the shell's own if these are None.
+    pub fn spawn_job(
+        &mut self,
+        stages: Vec<Command>,
+        stdout: Option<RawFd>,
+        stderr: Option<RawFd>,
+        mut group: Option<Group>,
+    ) -> Result<Job, Error> {
+        let name = stages
+            .iter()
+            .map(|c| {
+                c.args
+                    .iter()
+                    .map(|a| a.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+
+        let mut children = vec![];
+        let mut stdin = None;
+
+        for (i, cmd) in stages.iter().enumerate() {
+            let (next, out) = if i + 1 < stages.len() {
+                let (r, w) = cloexec_pipe()?;
+                (Some(r), Some(w))
+            } else {
+                (None, stdout)
+            };
+
+            let child = spawn(cmd, stdin, out, stderr, group);
+
+            // The children hold their own copies of the pipe ends now
+            if let Some(fd) = stdin {
+                close(fd).unwrap();
+            }
+            if let (Some(_), Some(fd)) = (next, out) {
+                close(fd).unwrap();
+            }
+            stdin = next;
+
+            let child = match child {
+                Ok(child) => child,
+                // Like in other shells, a stage that can't be executed
+                // doesn't stop the rest of the pipeline. It exits with 126 or
+                // 127, which is its status.
+                Err(SpawnError {
+                    code,
+                    msg,
+                    pid: Some(pid),
+                }) if stages.len() > 1 => {
+                    eprintln!("{}", Error::new(spawn_error_kind(code), msg).render());
+                    pid
+                }
+                Err(e) => {
+                    if let Some(fd) = stdin {
+                        close(fd).unwrap();
+                    }
+                    children.extend(e.pid);
+                    self.give_terminal(Some(self.shell_pgid));
+                    Job::new(None, children, name).update(true);
+
+                    self.set_status((e.code, None));
+                    return Err(Error::new(spawn_error_kind(e.code), e.msg));
+                }
+            };
+
+            // The rest of the pipeline joins the first process' group
+            if let Some(g) = group.as_mut().filter(|g| g.pgid.as_raw() == 0) {
+                g.pgid = child;
+            }
+            children.push(child);
+        }
+
+        let foreground = group.as_ref().is_some_and(|g| g.foreground);
+        let job = Job::new(group.map(|g| g.pgid), children, name);
+        if foreground {
+            self.give_terminal(job.pgid);
+        }
+
+        Ok(job)
+    }
+
+    // Run a list of commands as a foreground job. Output that isn't captured
+    // or redirected goes to the shell's own stdout and stderr. The exit code
+    // of the last stage is stored in $?
+    pub fn exec_pipeline(
+        &mut self,
+        stages: Vec<Command>,
+        capture_out: bool,
+        capture_err: bool,
+    ) -> Result<Output, Error> {
+        let redirected = stages.last().is_none_or(|c| c.redirects.stdout.is_some());
+        let capture = if capture_out && !redirected {
+            Some(cloexec_pipe()?)
+        } else {
+            None
+        };
+        let capture_err = if capture_err {
+            Some(cloexec_pipe()?)
+        } else {
+            None
+        };
+
+        let job = self.spawn_job(
+            stages,
+            capture.map(|(_, w)| w),
+            capture_err.map(|(_, w)| w),
+            self.new_group(true, capture.is_none() && capture_err.is_none()),
+        );
+
+        if let Err(e) = job {
+            for (r, w) in capture.into_iter().chain(capture_err) {
+                close(r).unwrap();
+                close(w).unwrap();
+            }
+            return Err(e);
+        }
+
+        // stderr is drained on its own thread so that neither pipe can fill
+        // up and block the children while we are reading the other
+        let err = capture_err.map(|(r, w)| {
+            close(w).unwrap();
+            std::thread::spawn(move || {
+                let err = read_all(r);
+                close(r).unwrap();
+                err
+            })
+        });
+
+        let out = capture.map(|(r, w)| {
+            close(w).unwrap();
+            let out = read_all(r);
+            close(r).unwrap();
+            out
+        });
+
+        let err = err.map(|t| t.join().unwrap());
+
+        self.wait_foreground(job.unwrap());
+
+        Ok(Output {
+            stdout: out,
+            stderr: err,
+            status: self.last_ret_code(),
+        })
+    }
+
+    // Run a pipeline the way a bare command is run. At the root the last
+    // stage writes to the terminal, otherwise its output is collected into a
+    // list of lines unless it has been redirected.
+    pub fn run_pipeline(&mut self, stages: Vec<Command>, root: bool) -> Result<SExpression, Error> {
+        let redirected = stages.last().is_none_or(|c| c.redirects.stdout.is_some());
+        if root || redirected {
+            self.exec_pipeline(stages, false, false)?;
+            return Ok(SExpression::Nil);
+        }
+
+        // The job stays in the shell's process group, so that it gets ctrl-c
+        // along with the shell and can use the terminal while the shell
+        // reads from it
+        let (r, w) = cloexec_pipe()?;
+        let job = self.spawn_job(stages, Some(w), None, None);
+        close(w).unwrap();
+
+        match job {
+            Ok(job) => Ok(SExpression::Stream(Stream::new(r, job, &self.status))),
+            Err(e) => {
+                close(r).unwrap();
+                Err(e)
+            }
+        }
+    }
+
+    // Wait for a command whose output is being thrown away, reading the rest
+    // of it so that the command isn't killed. Its status goes in $? once it
+    // has finished.
+    pub fn finish(&mut self, e: &SExpression) {
+        if let SExpression::Stream(lines) = e {
+            lines.read_to_end();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::Interpreter;
+
+    #[test]
+    fn a_stage_that_cannot_run_does_not_stop_the_pipeline() {
+        let bad = std::env::temp_dir().join(format!("lishp-bad-{}", std::process::id()));
+        std::fs::write(&bad, b"\x7fELF not really").unwrap();
+        std::fs::set_permissions(&bad, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let bad = bad.to_str().unwrap().to_string();
+
+        let mut it = Interpreter::load();
+        let e = it.eval(&format!("(list (first (| (echo hi) ({bad}) (wc -l))) $?)"));
+        assert_eq!(e.unwrap().to_string(), "(\"0\" 0)");
+
+        let e = it.eval(&format!("(list (count (| (echo hi) ({bad}))) $?)"));
+        assert_eq!(e.unwrap().to_string(), "(0 126)");
+
+        std::fs::remove_file(&bad).unwrap();
+    }
+}
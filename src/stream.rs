@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::rc::Rc;
+
+use nix::sys::signal::Signal;
+
+use crate::jobs::{Job, Status};
+use crate::{SExpression, Vector};
+
+// The output of a command as a list of lines that is only read as far as
+// something looks at it. The lines are a chain of cells, each read once
+// however many copies of the stream look at it, and freed once every copy
+// has moved past it.
+#[derive(Clone)]
+pub struct Stream {
+    // items consed onto the front of the lines
+    head: Vector,
+    cell: Cell,
+    source: Rc<RefCell<Source>>,
+}
+
+type Cell = Rc<RefCell<Node>>;
+
+enum Node {
+    Unread,
+    Line(SExpression, Link),
+    End,
+}
+
+// The cell after a line. A long chain of lines would otherwise be freed
+// recursively, one stack frame per line.
+struct Link(Option<Cell>);
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+
+        while let Some(Ok(cell)) = next.map(Rc::try_unwrap) {
+            next = match cell.into_inner() {
+                Node::Line(_, mut link) => link.0.take(),
+                _ => None,
+            };
+        }
+    }
+}
+
+struct Source {
+    // the rest of the output, until it has all been read
+    reader: Option<(BufReader<File>, Job)>,
+    // where the command's status goes when it finishes, and its number there
+    status: Rc<RefCell<Status>>,
+    cmd: u64,
+}
+
+impl Source {
+    // Read another line, or None at the end of the output
+    fn read_line(&mut self) -> Option<SExpression> {
+        let (reader, _) = self.reader.as_mut()?;
+
+        let mut buf = vec![];
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(n) if n > 0 => {
+                if buf.ends_with(b"\n") {
+                    buf.pop();
+                    if buf.ends_with(b"\r") {
+                        buf.pop();
+                    }
+                }
+                let line = String::from_utf8_lossy(&buf);
+                Some(SExpression::Str(line.as_ref().into()))
+            }
+            _ => {
+                self.finish(true);
+                None
+            }
+        }
+    }
+
+    // Stop reading and wait for the command to exit. A command whose output
+    // is no longer needed is sent SIGPIPE, as if it had written to the closed
+    // pipe, rather than waited for, and that isn't a failure of the command.
+    fn finish(&mut self, eof: bool) {
+        if let Some((reader, mut job)) = self.reader.take() {
+            drop(reader);
+            if !eof {
+                let _ = job.signal(Signal::SIGPIPE);
+            }
+            job.update(true);
+
+            let status = match job.status {
+                (_, Some(Signal::SIGPIPE)) if !eof => (0, None),
+                status => status,
+            };
+            self.status.borrow_mut().set(self.cmd, status);
+        }
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+impl Stream {
+    // Read the lines written to fd by a job, taking ownership of both. The
+    // job's status is stored in status when it finishes.
+    pub fn new(fd: RawFd, job: Job, status: &Rc<RefCell<Status>>) -> Self {
+        let reader = BufReader::new(unsafe { File::from_raw_fd(fd) });
+        let cmd = status.borrow_mut().start();
+
+        Self {
+            head: Vector::new(),
+            cell: Rc::new(RefCell::new(Node::Unread)),
+            source: Rc::new(RefCell::new(Source {
+                reader: Some((reader, job)),
+                status: status.clone(),
+                cmd,
+            })),
+        }
+    }
+
+    // Read the line in this stream's first cell if it hasn't been yet
+    fn force(&self) {
+        if matches!(*self.cell.borrow(), Node::Unread) {
+            let node = match self.source.borrow_mut().read_line() {
+                Some(line) => Node::Line(line, Link(Some(Rc::new(RefCell::new(Node::Unread))))),
+                None => Node::End,
+            };
+            *self.cell.borrow_mut() = node;
+        }
+    }
+
+    pub fn first(&self) -> Option<SExpression> {
+        if let Some(x) = self.head.first() {
+            return Some(x.clone());
+        }
+
+        self.force();
+        match &*self.cell.borrow() {
+            Node::Line(line, _) => Some(line.clone()),
+            _ => None,
+        }
+    }
+
+    // Everything but the first item, sharing the lines with this stream
+    pub fn rest(&self) -> Self {
+        if !self.head.is_empty() {
+            return Self {
+                head: self.head.rest(),
+                ..self.clone()
+            };
+        }
+
+        self.force();
+        let cell = match &*self.cell.borrow() {
+            Node::Line(_, Link(Some(next))) => next.clone(),
+            _ => self.cell.clone(),
+        };
+        Self {
+            cell,
+            ..self.clone()
+        }
+    }
+
+    // A stream with x in front of the items of this one
+    pub fn cons(&self, x: SExpression) -> Self {
+        Self {
+            head: self.head.clone().cons(x),
+            ..self.clone()
+        }
+    }
+
+    // The items of the stream, with lines read one at a time as they are
+    // needed
+    pub fn iter(&self) -> impl Iterator<Item = SExpression> + Clone {
+        self.clone().into_items()
+    }
+
+    // Like iter, but without keeping the lines already walked past alive, so
+    // that walking endless output takes constant memory
+    pub fn into_items(self) -> impl Iterator<Item = SExpression> + Clone {
+        let mut rest = self;
+
+        std::iter::from_fn(move || {
+            let line = rest.first()?;
+            rest = rest.rest();
+            Some(line)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first().is_none()
+    }
+
+    // Read the rest of the output, so that the command can finish
+    pub fn read_to_end(&self) {
+        for _ in self.iter() {}
+    }
+
+    // Read the rest of the output into a list
+    pub fn to_vector(&self) -> Vector {
+        self.iter().collect::<Vec<_>>().into()
+    }
+}
+
+// Streams are equal when they are the same lines of the same output
+impl PartialEq for Stream {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.cell, &other.cell) && self.head == other.head
+    }
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<stream>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use crate::{Interpreter, SExpression};
+
+    fn eval(it: &mut Interpreter, s: &str) -> SExpression {
+        it.eval(s).unwrap()
+    }
+
+    #[test]
+    fn compares_without_reading_everything() {
+        let mut it = Interpreter::load();
+
+        assert_eq!(eval(&mut it, "(empty? (yes))"), SExpression::Bool(false));
+        assert_eq!(eval(&mut it, "(= (yes) \"\")"), SExpression::Bool(false));
+        assert_eq!(eval(&mut it, "(= (yes) '(y n))"), SExpression::Bool(false));
+        assert_eq!(
+            eval(&mut it, "(= (seq 1 2) '(1 2))"),
+            SExpression::Bool(false)
+        );
+        assert_eq!(
+            eval(&mut it, "(= (seq 1 2) '(\"1\" \"2\"))"),
+            SExpression::Bool(true)
+        );
+        assert_eq!(eval(&mut it, "(empty? (true))"), SExpression::Bool(true));
+    }
+
+    #[test]
+    fn cons_keeps_the_stream_lazy() {
+        let mut it = Interpreter::load();
+
+        assert_eq!(eval(&mut it, "(first (cons 1 (yes)))"), SExpression::Int(1));
+        assert_eq!(
+            eval(&mut it, "(second (cons 1 (yes)))"),
+            SExpression::Str("y".into())
+        );
+        assert_eq!(
+            eval(&mut it, "(count (cons 0 (seq 1 3)))"),
+            SExpression::Int(4)
+        );
+    }
+
+    #[test]
+    fn sets_the_status_when_the_command_finishes() {
+        let mut it = Interpreter::load();
+        let mut show = |s: &str| eval(&mut it, s).to_string();
+
+        assert_eq!(show("(list (count (sh -c \"exit 3\")) $?)"), "(0 3)");
+        assert_eq!(
+            show("(let ((x (sh -c \"echo hi; exit 4\"))) (list (count x) $?))"),
+            "(1 4)"
+        );
+        // Output thrown away is read to the end first
+        assert_eq!(show("(do (sh -c \"exit 5\") $?)"), "5");
+        // A command stopped because its output wasn't needed didn't fail
+        assert_eq!(show("(list (first (yes)) $?)"), "(\"y\" 0)");
+        // and is stopped straight away rather than waited for
+        let start = Instant::now();
+        show("(first (sh -c \"echo a; sleep 5; echo b\"))");
+        assert!(start.elapsed() < Duration::from_secs(4));
+        // Output dropped late doesn't hide the status of a later command
+        assert_eq!(
+            show("(list (let ((x (ls))) (stdout (false))) $?)"),
+            "(() 1)"
+        );
+    }
+
+    #[test]
+    fn reading_the_status_never_reads_output() {
+        let mut it = Interpreter::load();
+        let mut show = |s: &str| eval(&mut it, s).to_string();
+
+        // The command hasn't finished while its output is unread, so $? is
+        // still the status of the last one that did
+        show("(false)");
+        assert_eq!(show("(let ((x (yes))) (list (first x) $?))"), "(\"y\" 1)");
+        assert_eq!(show("(do (false) (def x (yes)) $?)"), "1");
+    }
+
+    #[test]
+    fn lines_walked_past_are_freed() {
+        let mut it = Interpreter::load();
+
+        let SExpression::Stream(lines) = eval(&mut it, "(first (list (yes)))") else {
+            panic!("expected a stream");
+        };
+        let first = Rc::downgrade(&lines.cell);
+
+        let mut items = lines.into_items();
+        items.nth(1000);
+        assert!(first.upgrade().is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use std::rc::Rc;
+
+use crate::SExpression;
+
+// An immutable list whose items are shared between copies of it. The items
+// are stored last to first, so that both taking the rest of a list and consing
+// onto it happen at the end of the Vec: rest just shortens the view, and cons
+// pushes in place when nothing else can see past the end of the view.
+#[derive(Clone, Default)]
+pub struct Vector {
+    items: Rc<Vec<SExpression>>,
+    len: usize,
+}
+
+impl Vector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn first(&self) -> Option<&SExpression> {
+        self.len.checked_sub(1).map(|i| &self.items[i])
+    }
+
+    // Everything but the first item, sharing the items with this list
+    pub fn rest(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            len: self.len.saturating_sub(1),
+        }
+    }
+
+    // A list with x in front of the items of this one
+    pub fn cons(mut self, x: SExpression) -> Self {
+        match Rc::get_mut(&mut self.items) {
+            Some(items) => {
+                items.truncate(self.len);
+                items.push(x);
+            }
+            None => {
+                let mut items = Vec::with_capacity(self.len + 1);
+                items.extend_from_slice(&self.items[..self.len]);
+                items.push(x);
+                self.items = Rc::new(items);
+            }
+        }
+
+        self.len += 1;
+        self
+    }
+
+    pub fn iter(&self) -> std::iter::Rev<std::slice::Iter<'_, SExpression>> {
+        self.items[..self.len].iter().rev()
+    }
+}
+
+impl From<Vec<SExpression>> for Vector {
+    fn from(mut items: Vec<SExpression>) -> Self {
+        items.reverse();
+
+        Self {
+            len: items.len(),
+            items: Rc::new(items),
+        }
+    }
+}
+
+impl<const N: usize> From<[SExpression; N]> for Vector {
+    fn from(items: [SExpression; N]) -> Self {
+        Vec::from(items).into()
+    }
+}
+
+impl FromIterator<SExpression> for Vector {
+    fn from_iter<I: IntoIterator<Item = SExpression>>(iter: I) -> Self {
+        iter.into_iter().collect::<Vec<_>>().into()
+    }
+}
+
+impl IntoIterator for Vector {
+    type Item = SExpression;
+    type IntoIter = std::iter::Rev<std::vec::IntoIter<SExpression>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let len = self.len;
+        let mut items = Rc::try_unwrap(self.items).unwrap_or_else(|items| items[..len].to_vec());
+
+        items.truncate(len);
+        items.into_iter().rev()
+    }
+}
+
+impl<'a> IntoIterator for &'a Vector {
+    type Item = &'a SExpression;
+    type IntoIter = std::iter::Rev<std::slice::Iter<'a, SExpression>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl PartialEq for Vector {
+    fn eq(&self, other: &Self) -> bool {
+        self.items[..self.len] == other.items[..other.len]
+    }
+}
+
+impl std::fmt::Debug for Vector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}